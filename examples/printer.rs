@@ -10,12 +10,16 @@ fn main() {
     if let Ok(oplog) = Oplog::new(&client) {
         for operation in oplog {
             match operation {
-                Operation::Noop { timestamp, .. } => println!("No-op at {}", timestamp),
-                Operation::Insert { timestamp, .. } => println!("Insert at {}", timestamp),
-                Operation::Update { timestamp, .. } => println!("Update at {}", timestamp),
-                Operation::Delete { timestamp, .. } => println!("Delete at {}", timestamp),
-                Operation::Command { timestamp, .. } => println!("Command at {}", timestamp),
-                Operation::ApplyOps { timestamp, .. } => println!("ApplyOps at {}", timestamp),
+                Operation::Noop { ref meta, .. } => println!("No-op at {}", meta.optime),
+                Operation::Insert { ref meta, .. } => println!("Insert at {}", meta.optime),
+                Operation::Update { ref meta, .. } => println!("Update at {}", meta.optime),
+                Operation::Delete { ref meta, .. } => println!("Delete at {}", meta.optime),
+                Operation::Command { ref meta, .. } => println!("Command at {}", meta.optime),
+                Operation::ApplyOps { ref meta, .. } => println!("ApplyOps at {}", meta.optime),
+                Operation::Transaction { ref meta, .. } => {
+                    println!("Transaction at {}", meta.optime)
+                }
+                Operation::Unknown { ref meta, .. } => println!("Unknown at {}", meta.optime),
             }
         }
     }
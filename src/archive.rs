@@ -0,0 +1,821 @@
+//! The archive module is responsible for recording the oplog into rolling segment files so that
+//! its history can be kept for longer than the server's capped collection allows.
+//!
+//! An archive is a directory of segments, each a file of concatenated BSON oplog documents
+//! optionally compressed with gzip (with the `gzip` feature) or zstd (with the `zstd` feature),
+//! and an `index.bson` recording the first and last position of every segment in order.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::vec;
+
+use bson::{self, Document};
+#[cfg(feature = "gzip")]
+use flate2;
+#[cfg(feature = "zstd")]
+use zstd;
+
+use {BsonSource, Error, OplogSource, Oplog, OpTime, Result};
+
+/// The name of the index file within an archive.
+const INDEX: &str = "index.bson";
+
+/// How to compress the segments of an archive.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    /// No compression.
+    None,
+    /// Gzip compression.
+    #[cfg(feature = "gzip")]
+    Gzip,
+    /// Zstandard compression.
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Compression {
+    /// Returns the file extension of segments with this compression.
+    fn extension(&self) -> &'static str {
+        match *self {
+            Compression::None => "bson",
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => "bson.gz",
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => "bson.zst",
+        }
+    }
+}
+
+/// When to start a new segment of an archive.
+///
+/// A segment is rotated as soon as either limit is reached.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rotation {
+    /// The number of uncompressed bytes after which to start a new segment or `None` for no limit.
+    pub max_bytes: Option<u64>,
+    /// The span of oplog time after which to start a new segment or `None` for no limit.
+    pub max_span: Option<Duration>,
+}
+
+impl Default for Rotation {
+    /// Returns a `Rotation` starting a new segment every 64 MiB or hour of operations.
+    fn default() -> Rotation {
+        Rotation {
+            max_bytes: Some(64 * 1024 * 1024),
+            max_span: Some(Duration::from_secs(60 * 60)),
+        }
+    }
+}
+
+/// A segment of an archive as recorded in its index.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchiveSegment {
+    /// The name of the segment's file within the archive.
+    pub file: String,
+    /// The position of the first operation in the segment.
+    pub first: OpTime,
+    /// The position of the last operation in the segment.
+    pub last: OpTime,
+}
+
+impl ArchiveSegment {
+    /// Try to create a new `ArchiveSegment` from its BSON document in an index.
+    fn from_document(document: &Document) -> Result<ArchiveSegment> {
+        Ok(ArchiveSegment {
+            file: document.get_str("file")?.into(),
+            first: OpTime::from_timestamp(document.get_time_stamp("first")?,
+                                          document.get_i64("firstTerm").ok()),
+            last: OpTime::from_timestamp(document.get_time_stamp("last")?,
+                                         document.get_i64("lastTerm").ok()),
+        })
+    }
+
+    /// Returns the BSON document of this segment in an index.
+    ///
+    /// Positions are recorded as timestamps with any terms alongside them.
+    fn to_document(&self) -> Document {
+        let mut document = doc! {
+            "file" => (self.file.clone()),
+            "first" => (self.first),
+            "last" => (self.last)
+        };
+
+        if let Some(term) = self.first.term {
+            document.insert("firstTerm", term);
+        }
+
+        if let Some(term) = self.last.term {
+            document.insert("lastTerm", term);
+        }
+
+        document
+    }
+}
+
+/// A recorder of oplog documents into an archive.
+///
+/// Segments are only added to the index once they are rotated or the archiver is finished or
+/// dropped. Should the archiver crash before then, the unfinished segment is removed when the
+/// archive is next opened for recording.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate mongodb;
+/// # extern crate oplog;
+/// use mongodb::{Client, ThreadedClient};
+/// use oplog::{Archiver, Compression, Oplog, Rotation};
+///
+/// # fn main() {
+/// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+/// let mut archiver = Archiver::new("archive", Rotation::default(), Compression::None)
+///                        .expect("Failed to open archive.");
+///
+/// if let Ok(mut oplog) = Oplog::new(&client) {
+///     archiver.record(&mut oplog).expect("Failed to record oplog.");
+/// }
+/// # }
+/// ```
+pub struct Archiver {
+    /// The directory of the archive.
+    directory: PathBuf,
+    /// When to start a new segment.
+    rotation: Rotation,
+    /// How to compress new segments.
+    compression: Compression,
+    /// Every finished segment in order.
+    segments: Vec<ArchiveSegment>,
+    /// The segment currently being written, if any.
+    current: Option<OpenSegment>,
+}
+
+impl Archiver {
+    /// Try to open the archive in the given directory for recording, creating it if necessary.
+    ///
+    /// Any existing segments are kept and new segments are added after them. Segment files missing
+    /// from the index, e.g. as left behind by an archiver that crashed before finishing them, are
+    /// removed as they may be incomplete.
+    pub fn new<P: AsRef<Path>>(directory: P,
+                               rotation: Rotation,
+                               compression: Compression)
+                               -> Result<Archiver> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)?;
+        let segments = read_index(&directory)?;
+        remove_unindexed(&directory, &segments)?;
+
+        Ok(Archiver {
+            directory: directory,
+            rotation: rotation,
+            compression: compression,
+            segments: segments,
+            current: None,
+        })
+    }
+
+    /// Returns every finished segment of the archive in order.
+    pub fn segments(&self) -> &[ArchiveSegment] {
+        &self.segments
+    }
+
+    /// Record the raw document of every operation read from the given oplog until it ends or
+    /// returns an error, finishing the archive afterwards.
+    ///
+    /// Documents are recorded whether or not they can be converted to an `Operation`.
+    pub fn record<S: OplogSource>(&mut self, oplog: &mut Oplog<S>) -> Result<()> {
+        let lenient = oplog.replace_lenient(true);
+        let result = self.write_entries(oplog);
+        oplog.replace_lenient(lenient);
+        result?;
+
+        self.finish()
+    }
+
+    /// Record the raw document of every operation read from the given oplog until it ends or
+    /// returns an error.
+    fn write_entries<S: OplogSource>(&mut self, oplog: &mut Oplog<S>) -> Result<()> {
+        while let Some(entry) = oplog.next_entry() {
+            let (_, document) = entry?;
+            self.write(&document)?;
+        }
+
+        Ok(())
+    }
+
+    /// Record a single raw oplog document, starting a new segment if necessary.
+    ///
+    /// Returns an error if the document has no position.
+    pub fn write(&mut self, document: &Document) -> Result<()> {
+        let optime = OpTime::from_document(document)?;
+
+        if self.is_full(optime) {
+            self.finish()?;
+        }
+
+        if self.current.is_none() {
+            self.current = Some(self.create(optime)?);
+        }
+
+        let mut bytes = Vec::new();
+        bson::encode_document(&mut bytes, document)?;
+
+        if let Some(ref mut current) = self.current {
+            current.writer.write_all(&bytes)?;
+            current.bytes += bytes.len() as u64;
+            current.segment.last = optime;
+        }
+
+        Ok(())
+    }
+
+    /// Flush the segment being written and write the index of every finished segment.
+    ///
+    /// The segment being written is not indexed until it is finished as a compressed segment
+    /// cannot be read back before then.
+    pub fn flush(&mut self) -> Result<()> {
+        if let Some(ref mut current) = self.current {
+            current.writer.flush()?;
+        }
+
+        write_index(&self.directory, &self.segments)
+    }
+
+    /// Finish the segment being written, if any, and write the index.
+    pub fn finish(&mut self) -> Result<()> {
+        if let Some(current) = self.current.take() {
+            current.writer.finish()?;
+            self.segments.push(current.segment);
+        }
+
+        write_index(&self.directory, &self.segments)
+    }
+
+    /// Returns whether the segment being written should be rotated before an operation at the
+    /// given position.
+    fn is_full(&self, optime: OpTime) -> bool {
+        let current = match self.current {
+            Some(ref current) => current,
+            None => return false,
+        };
+        let too_big = match self.rotation.max_bytes {
+            Some(max_bytes) => current.bytes >= max_bytes,
+            None => false,
+        };
+        let too_long = match self.rotation.max_span {
+            Some(max_span) => {
+                optime.seconds.saturating_sub(current.segment.first.seconds) as u64 >=
+                max_span.as_secs()
+            }
+            None => false,
+        };
+
+        too_big || too_long
+    }
+
+    /// Create a new segment starting with an operation at the given position.
+    ///
+    /// Any existing file for the segment that is not in the index is overwritten.
+    fn create(&self, first: OpTime) -> Result<OpenSegment> {
+        let file = format!("{:010}-{:010}.{}",
+                           first.seconds,
+                           first.increment,
+                           self.compression.extension());
+        let indexed = self.segments.iter().any(|segment| segment.file == file);
+        let output = OpenOptions::new()
+                         .write(true)
+                         .create_new(indexed)
+                         .create(true)
+                         .truncate(true)
+                         .open(self.directory.join(&file))?;
+
+        Ok(OpenSegment {
+            writer: SegmentWriter::new(BufWriter::new(output), self.compression)?,
+            bytes: 0,
+            segment: ArchiveSegment {
+                file: file,
+                first: first,
+                last: first,
+            },
+        })
+    }
+}
+
+impl Drop for Archiver {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// A segment being written.
+struct OpenSegment {
+    /// The possibly compressed output.
+    writer: SegmentWriter,
+    /// The number of uncompressed bytes written.
+    bytes: u64,
+    /// The segment as it will be recorded in the index.
+    segment: ArchiveSegment,
+}
+
+/// The possibly compressed output of a segment.
+enum SegmentWriter {
+    Plain(BufWriter<File>),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<BufWriter<File>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::Encoder<BufWriter<File>>),
+}
+
+impl SegmentWriter {
+    /// Returns a new writer compressing to the given output.
+    fn new(output: BufWriter<File>, compression: Compression) -> io::Result<SegmentWriter> {
+        match compression {
+            Compression::None => Ok(SegmentWriter::Plain(output)),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                let encoder = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+
+                Ok(SegmentWriter::Gzip(encoder))
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(SegmentWriter::Zstd(zstd::stream::Encoder::new(output, 0)?)),
+        }
+    }
+
+    /// Complete any compression and flush the output.
+    fn finish(self) -> io::Result<()> {
+        match self {
+            SegmentWriter::Plain(mut output) => output.flush(),
+            #[cfg(feature = "gzip")]
+            SegmentWriter::Gzip(encoder) => encoder.finish()?.flush(),
+            #[cfg(feature = "zstd")]
+            SegmentWriter::Zstd(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for SegmentWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            SegmentWriter::Plain(ref mut output) => output.write(buf),
+            #[cfg(feature = "gzip")]
+            SegmentWriter::Gzip(ref mut encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            SegmentWriter::Zstd(ref mut encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            SegmentWriter::Plain(ref mut output) => output.flush(),
+            #[cfg(feature = "gzip")]
+            SegmentWriter::Gzip(ref mut encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            SegmentWriter::Zstd(ref mut encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// A source of documents read back from every segment of an archive in order.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use oplog::{ArchiveSource, Oplog};
+///
+/// let source = ArchiveSource::open("archive").expect("Failed to open archive.");
+///
+/// for operation in Oplog::from_source(source) {
+///     // Do something with operation...
+/// }
+/// ```
+pub struct ArchiveSource {
+    /// The directory of the archive.
+    directory: PathBuf,
+    /// The segments yet to be read.
+    segments: vec::IntoIter<ArchiveSegment>,
+    /// The segment being read, if any.
+    current: Option<BsonSource<Box<dyn Read>>>,
+}
+
+impl ArchiveSource {
+    /// Try to open the archive in the given directory for reading.
+    ///
+    /// Only the segments recorded in its index are read.
+    pub fn open<P: AsRef<Path>>(directory: P) -> Result<ArchiveSource> {
+        let directory = directory.as_ref().to_path_buf();
+        let segments = read_index(&directory)?;
+
+        Ok(ArchiveSource {
+            directory: directory,
+            segments: segments.into_iter(),
+            current: None,
+        })
+    }
+
+    /// Open the given segment for reading, decompressing it if necessary.
+    fn open_segment(&self, segment: &ArchiveSegment) -> Result<Box<dyn Read>> {
+        let path = self.directory.join(&segment.file);
+        let input = File::open(&path)?;
+
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("bson") => Ok(Box::new(input)),
+            #[cfg(feature = "gzip")]
+            Some("gz") => Ok(Box::new(flate2::read::GzDecoder::new(input))),
+            #[cfg(feature = "zstd")]
+            Some("zst") => Ok(Box::new(zstd::stream::Decoder::new(input)?)),
+            _ => {
+                Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput,
+                                             format!("unsupported segment {}", segment.file))))
+            }
+        }
+    }
+}
+
+impl OplogSource for ArchiveSource {
+    fn next_document(&mut self) -> Option<Result<Document>> {
+        loop {
+            if let Some(ref mut current) = self.current {
+                if let Some(result) = current.next_document() {
+                    return Some(result);
+                }
+            }
+
+            let segment = self.segments.next()?;
+
+            match self.open_segment(&segment) {
+                Ok(input) => self.current = Some(BsonSource::new(input)),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+/// Try to read the index of the archive in the given directory, returning no segments if there is
+/// none.
+fn read_index(directory: &Path) -> Result<Vec<ArchiveSegment>> {
+    let input = match File::open(directory.join(INDEX)) {
+        Ok(input) => input,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut source = BsonSource::new(input);
+    let mut segments = Vec::new();
+
+    while let Some(document) = source.next_document() {
+        segments.push(ArchiveSegment::from_document(&document?)?);
+    }
+
+    Ok(segments)
+}
+
+/// Remove every segment file in the given directory that is not one of the given segments.
+fn remove_unindexed(directory: &Path, segments: &[ArchiveSegment]) -> Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let file = match entry.file_name().into_string() {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+
+        if is_segment(&file) && !segments.iter().any(|segment| segment.file == file) {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns whether the given file name is that of a segment with any compression.
+fn is_segment(file: &str) -> bool {
+    let (stem, extension) = match file.find('.') {
+        Some(index) => file.split_at(index),
+        None => return false,
+    };
+    let mut positions = stem.split('-');
+    let is_position = |position: Option<&str>| match position {
+        Some(position) => position.len() == 10 && position.bytes().all(|b| b.is_ascii_digit()),
+        None => false,
+    };
+
+    is_position(positions.next()) && is_position(positions.next()) && positions.next().is_none() &&
+    [".bson", ".bson.gz", ".bson.zst"].contains(&extension)
+}
+
+/// Replace the index of the archive in the given directory with the given segments.
+fn write_index(directory: &Path, segments: &[ArchiveSegment]) -> Result<()> {
+    let path = directory.join(INDEX);
+    let temporary = path.with_extension("bson.tmp");
+
+    {
+        let mut output = BufWriter::new(File::create(&temporary)?);
+
+        for segment in segments {
+            bson::encode_document(&mut output, &segment.to_document())?;
+        }
+
+        output.flush()?;
+    }
+
+    fs::rename(temporary, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::mem;
+    use std::path::PathBuf;
+    use std::process;
+    use std::time::Duration;
+
+    use bson::{Bson, Document};
+    use {Oplog, OplogSource, OpTime};
+    use super::{is_segment, Archiver, ArchiveSegment, ArchiveSource, Compression, Rotation};
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("oplog-archive-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&directory);
+
+        directory
+    }
+
+    fn insert(seconds: i64) -> Document {
+        doc! {
+            "ts" => (Bson::TimeStamp(seconds << 32 | 1)),
+            "v" => 2,
+            "op" => "i",
+            "ns" => "foo.bar",
+            "o" => { "_id" => seconds }
+        }
+    }
+
+    fn read(directory: &PathBuf) -> Vec<Document> {
+        let mut source = ArchiveSource::open(directory).unwrap();
+        let mut documents = Vec::new();
+
+        while let Some(document) = source.next_document() {
+            documents.push(document.unwrap());
+        }
+
+        documents
+    }
+
+    #[test]
+    fn archiver_rotates_segments_by_size() {
+        let directory = directory("size");
+        let rotation = Rotation {
+            max_bytes: Some(1),
+            max_span: None,
+        };
+
+        {
+            let mut archiver = Archiver::new(&directory, rotation, Compression::None).unwrap();
+            archiver.write(&insert(1)).unwrap();
+            archiver.write(&insert(2)).unwrap();
+            archiver.finish().unwrap();
+
+            assert_eq!(archiver.segments(),
+                       &[ArchiveSegment {
+                             file: "0000000001-0000000001.bson".into(),
+                             first: OpTime::new(1, 1, None),
+                             last: OpTime::new(1, 1, None),
+                         },
+                         ArchiveSegment {
+                             file: "0000000002-0000000001.bson".into(),
+                             first: OpTime::new(2, 1, None),
+                             last: OpTime::new(2, 1, None),
+                         }]);
+        }
+
+        assert_eq!(read(&directory), vec![insert(1), insert(2)]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn archiver_rotates_segments_by_span() {
+        let directory = directory("span");
+        let rotation = Rotation {
+            max_bytes: None,
+            max_span: Some(Duration::from_secs(60)),
+        };
+
+        {
+            let mut archiver = Archiver::new(&directory, rotation, Compression::None).unwrap();
+            archiver.write(&insert(1)).unwrap();
+            archiver.write(&insert(60)).unwrap();
+            archiver.write(&insert(61)).unwrap();
+            archiver.finish().unwrap();
+
+            assert_eq!(archiver.segments().len(), 2);
+            assert_eq!(archiver.segments()[0].last, OpTime::new(60, 1, None));
+        }
+
+        assert_eq!(read(&directory), vec![insert(1), insert(60), insert(61)]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn archiver_appends_to_existing_archives() {
+        let directory = directory("append");
+
+        {
+            let mut archiver = Archiver::new(&directory, Rotation::default(), Compression::None)
+                                   .unwrap();
+            archiver.write(&insert(1)).unwrap();
+        }
+
+        {
+            let mut archiver = Archiver::new(&directory, Rotation::default(), Compression::None)
+                                   .unwrap();
+            archiver.write(&insert(2)).unwrap();
+        }
+
+        assert_eq!(read(&directory), vec![insert(1), insert(2)]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn archiver_overwrites_unfinished_segments() {
+        let directory = directory("crash");
+
+        {
+            let mut archiver = Archiver::new(&directory, Rotation::default(), Compression::None)
+                                   .unwrap();
+            archiver.write(&insert(1)).unwrap();
+            archiver.write(&insert(2)).unwrap();
+            mem::forget(archiver);
+        }
+
+        {
+            let mut archiver = Archiver::new(&directory, Rotation::default(), Compression::None)
+                                   .unwrap();
+            archiver.write(&insert(1)).unwrap();
+        }
+
+        assert_eq!(read(&directory), vec![insert(1)]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn archiver_removes_unfinished_segments() {
+        let directory = directory("orphan");
+
+        {
+            let mut archiver = Archiver::new(&directory, Rotation::default(), Compression::None)
+                                   .unwrap();
+            archiver.write(&insert(1)).unwrap();
+            archiver.write(&insert(2)).unwrap();
+            mem::forget(archiver);
+        }
+
+        fs::write(directory.join("notes.txt"), "Not a segment").unwrap();
+
+        {
+            let mut archiver = Archiver::new(&directory, Rotation::default(), Compression::None)
+                                   .unwrap();
+            archiver.write(&insert(5)).unwrap();
+        }
+
+        assert!(!directory.join("0000000001-0000000001.bson").exists());
+        assert!(directory.join("notes.txt").exists());
+        assert_eq!(read(&directory), vec![insert(5)]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn archiver_only_indexes_finished_segments_when_flushed() {
+        let directory = directory("flush");
+        let mut archiver = Archiver::new(&directory, Rotation::default(), Compression::None)
+                               .unwrap();
+        archiver.write(&insert(1)).unwrap();
+        archiver.flush().unwrap();
+
+        assert_eq!(read(&directory), vec![]);
+
+        archiver.finish().unwrap();
+
+        assert_eq!(read(&directory), vec![insert(1)]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn is_segment_matches_segment_files() {
+        assert!(is_segment("0000000001-0000000002.bson"));
+        assert!(is_segment("0000000001-0000000002.bson.gz"));
+        assert!(is_segment("0000000001-0000000002.bson.zst"));
+        assert!(!is_segment("index.bson"));
+        assert!(!is_segment("index.bson.tmp"));
+        assert!(!is_segment("0000000001-0000000002.txt"));
+        assert!(!is_segment("1-2.bson"));
+    }
+
+    #[test]
+    fn archiver_does_not_overwrite_indexed_segments() {
+        let directory = directory("indexed");
+
+        {
+            let mut archiver = Archiver::new(&directory, Rotation::default(), Compression::None)
+                                   .unwrap();
+            archiver.write(&insert(1)).unwrap();
+        }
+
+        {
+            let mut archiver = Archiver::new(&directory, Rotation::default(), Compression::None)
+                                   .unwrap();
+
+            assert!(archiver.write(&insert(1)).is_err());
+        }
+
+        assert_eq!(read(&directory), vec![insert(1)]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn archiver_records_unconvertible_documents() {
+        let directory = directory("record");
+        let unknown = doc! {
+            "ts" => (Bson::TimeStamp(2 << 32 | 1)),
+            "v" => 2,
+            "op" => "x",
+            "ns" => "foo.bar",
+            "o" => {}
+        };
+        let documents = vec![insert(1), unknown.clone(), insert(3)];
+
+        {
+            let mut oplog = Oplog::from_source(documents.into_iter());
+            let mut archiver = Archiver::new(&directory, Rotation::default(), Compression::None)
+                                   .unwrap();
+            archiver.record(&mut oplog).unwrap();
+        }
+
+        assert_eq!(read(&directory), vec![insert(1), unknown, insert(3)]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn archiver_indexes_terms() {
+        let directory = directory("terms");
+        let mut document = insert(1);
+        document.insert("t", 2i64);
+
+        {
+            let mut archiver = Archiver::new(&directory, Rotation::default(), Compression::None)
+                                   .unwrap();
+            archiver.write(&document).unwrap();
+        }
+
+        let archiver = Archiver::new(&directory, Rotation::default(), Compression::None).unwrap();
+
+        assert_eq!(archiver.segments()[0].first, OpTime::new(1, 1, Some(2)));
+        assert_eq!(archiver.segments()[0].last, OpTime::new(1, 1, Some(2)));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    fn assert_round_trips(name: &str, compression: Compression, extension: &str) {
+        let directory = directory(name);
+        let rotation = Rotation {
+            max_bytes: Some(1),
+            max_span: None,
+        };
+
+        {
+            let mut archiver = Archiver::new(&directory, rotation, compression).unwrap();
+            archiver.write(&insert(1)).unwrap();
+            archiver.write(&insert(2)).unwrap();
+            archiver.finish().unwrap();
+
+            assert!(archiver.segments().iter().all(|segment| segment.file.ends_with(extension)));
+        }
+
+        assert_eq!(read(&directory), vec![insert(1), insert(2)]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn archiver_round_trips_gzip_segments() {
+        assert_round_trips("gzip", Compression::Gzip, ".bson.gz");
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn archiver_round_trips_zstd_segments() {
+        assert_round_trips("zstd", Compression::Zstd, ".bson.zst");
+    }
+
+    #[test]
+    fn archiver_rejects_documents_without_positions() {
+        let directory = directory("invalid");
+        let mut archiver = Archiver::new(&directory, Rotation::default(), Compression::None)
+                               .unwrap();
+
+        assert!(archiver.write(&doc! { "op" => "n" }).is_err());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
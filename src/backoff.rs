@@ -0,0 +1,99 @@
+//! The backoff module is responsible for deciding how long to wait between attempts to reconnect
+//! to the oplog after its cursor is lost, e.g. due to a replica set election or network error.
+
+use std::cmp;
+use std::time::Duration;
+
+/// An exponential backoff between attempts to reconnect to the oplog.
+///
+/// The delay starts at `initial` and doubles after every failed attempt up to `max`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Backoff {
+    /// The delay before the first attempt to reconnect.
+    pub initial: Duration,
+    /// The longest delay between attempts to reconnect.
+    pub max: Duration,
+    /// The number of attempts to make before giving up or `None` to retry forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Backoff {
+    /// Returns a new `Backoff` with the given initial and maximum delays that retries forever.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use oplog::Backoff;
+    ///
+    /// let backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(30));
+    /// ```
+    pub fn new(initial: Duration, max: Duration) -> Backoff {
+        Backoff {
+            initial: initial,
+            max: max,
+            max_attempts: None,
+        }
+    }
+
+    /// Returns the delay before the given attempt, counting from zero.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+        let delay = self.initial.checked_mul(factor).unwrap_or(self.max);
+
+        cmp::min(delay, self.max)
+    }
+
+    /// Returns whether another attempt should be made after the given number of attempts.
+    pub fn retry(&self, attempts: u32) -> bool {
+        match self.max_attempts {
+            Some(max_attempts) => attempts < max_attempts,
+            None => true,
+        }
+    }
+}
+
+impl Default for Backoff {
+    /// Returns a `Backoff` starting at 100 milliseconds up to 30 seconds that retries forever.
+    fn default() -> Backoff {
+        Backoff::new(Duration::from_millis(100), Duration::from_secs(30))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::Backoff;
+
+    #[test]
+    fn backoff_doubles_delays() {
+        let backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(30));
+
+        assert_eq!(backoff.delay(0), Duration::from_millis(100));
+        assert_eq!(backoff.delay(1), Duration::from_millis(200));
+        assert_eq!(backoff.delay(3), Duration::from_millis(800));
+    }
+
+    #[test]
+    fn backoff_limits_delays() {
+        let backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+
+        assert_eq!(backoff.delay(4), Duration::from_secs(1));
+        assert_eq!(backoff.delay(40), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn backoff_retries_forever_by_default() {
+        let backoff = Backoff::default();
+
+        assert!(backoff.retry(u32::MAX));
+    }
+
+    #[test]
+    fn backoff_limits_attempts() {
+        let backoff = Backoff { max_attempts: Some(3), ..Backoff::default() };
+
+        assert!(backoff.retry(2));
+        assert!(!backoff.retry(3));
+    }
+}
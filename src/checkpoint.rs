@@ -0,0 +1,174 @@
+//! The checkpoint module is responsible for durably recording the position of the last operation
+//! processed by a consumer so that it can resume from there after a restart.
+//!
+//! Positions are never recorded automatically: a consumer should only store the position of an
+//! operation once it has finished processing it.
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use bson::{self, Bson, Document};
+
+use {OpTime, Result};
+
+/// A store of the position of the last processed operation.
+pub trait Checkpoint {
+    /// Returns the stored position, if any.
+    fn load(&self) -> Result<Option<OpTime>>;
+
+    /// Replace the stored position with the given one.
+    fn store(&mut self, optime: OpTime) -> Result<()>;
+}
+
+/// A checkpoint stored in a single file as a BSON document.
+///
+/// The file is replaced atomically on every store so that a crash never leaves a partially written
+/// position behind.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate mongodb;
+/// # extern crate oplog;
+/// use mongodb::{Client, ThreadedClient};
+/// use oplog::{Checkpoint, FileCheckpoint, OplogBuilder};
+///
+/// # fn main() {
+/// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+/// let mut checkpoint = FileCheckpoint::new("oplog.checkpoint");
+/// let oplog = OplogBuilder::new(&client).checkpoint(&checkpoint).build()
+///     .expect("Failed to read oplog.");
+///
+/// for operation in oplog {
+///     // Do something with operation...
+///     checkpoint.store(operation.optime()).expect("Failed to store checkpoint.");
+/// }
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileCheckpoint {
+    /// The path of the checkpoint file.
+    path: PathBuf,
+}
+
+impl FileCheckpoint {
+    /// Returns a new checkpoint stored at the given path.
+    ///
+    /// The file need not exist until a position is first stored.
+    pub fn new<P: AsRef<Path>>(path: P) -> FileCheckpoint {
+        FileCheckpoint { path: path.as_ref().to_path_buf() }
+    }
+}
+
+impl Checkpoint for FileCheckpoint {
+    fn load(&self) -> Result<Option<OpTime>> {
+        let mut input = match File::open(&self.path) {
+            Ok(input) => input,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let document = bson::decode_document(&mut input)?;
+
+        OpTime::from_document(&document).map(Some)
+    }
+
+    fn store(&mut self, optime: OpTime) -> Result<()> {
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+
+        {
+            let mut output = File::create(&temporary)?;
+            bson::encode_document(&mut output, &to_document(optime))?;
+            output.sync_all()?;
+        }
+
+        fs::rename(temporary, &self.path)?;
+
+        Ok(())
+    }
+}
+
+/// A checkpoint held in memory, e.g. for testing or consumers that can safely restart from the
+/// beginning.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MemoryCheckpoint {
+    /// The stored position, if any.
+    optime: Option<OpTime>,
+}
+
+impl MemoryCheckpoint {
+    /// Returns a new, empty checkpoint.
+    pub fn new() -> MemoryCheckpoint {
+        MemoryCheckpoint::default()
+    }
+}
+
+impl Checkpoint for MemoryCheckpoint {
+    fn load(&self) -> Result<Option<OpTime>> {
+        Ok(self.optime)
+    }
+
+    fn store(&mut self, optime: OpTime) -> Result<()> {
+        self.optime = Some(optime);
+
+        Ok(())
+    }
+}
+
+/// Returns a document recording the given position in the same fields as an oplog entry.
+fn to_document(optime: OpTime) -> Document {
+    let mut document = doc! { "ts" => optime };
+
+    if let Some(term) = optime.term {
+        document.insert("t", Bson::I64(term));
+    }
+
+    document
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use std::path::PathBuf;
+
+    use OpTime;
+    use super::{Checkpoint, FileCheckpoint, MemoryCheckpoint};
+
+    fn path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("oplog-checkpoint-{}-{}", name, process::id()));
+        let _ = fs::remove_file(&path);
+
+        path
+    }
+
+    #[test]
+    fn file_checkpoint_is_empty_without_a_file() {
+        let checkpoint = FileCheckpoint::new(path("empty"));
+
+        assert_eq!(checkpoint.load().unwrap(), None);
+    }
+
+    #[test]
+    fn file_checkpoint_round_trips_positions() {
+        let path = path("round-trip");
+        let mut checkpoint = FileCheckpoint::new(&path);
+        checkpoint.store(OpTime::new(1479561394, 1, Some(2))).unwrap();
+        checkpoint.store(OpTime::new(1479561395, 3, None)).unwrap();
+
+        assert_eq!(FileCheckpoint::new(&path).load().unwrap(),
+                   Some(OpTime::new(1479561395, 3, None)));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn memory_checkpoint_round_trips_positions() {
+        let mut checkpoint = MemoryCheckpoint::new();
+        assert_eq!(checkpoint.load().unwrap(), None);
+
+        checkpoint.store(OpTime::new(1479561394, 1, Some(2))).unwrap();
+
+        assert_eq!(checkpoint.load().unwrap(), Some(OpTime::new(1479561394, 1, Some(2))));
+    }
+}
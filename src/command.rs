@@ -0,0 +1,393 @@
+//! The command module is responsible for converting the BSON documents of MongoDB command
+//! operations into specific `CommandKind`s, e.g. the creation or renaming of a collection.
+//!
+//! Commands are identified by the name of the first field in their document with its value
+//! typically being the collection the command applies to. Any command we do not know how to convert
+//! is preserved as its original BSON document.
+
+use std::fmt;
+
+use bson::{Bson, Document};
+use {OpTime, Result};
+
+/// A MongoDB command as recorded in the oplog.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CommandKind {
+    /// The creation of a collection.
+    Create {
+        /// The name of the collection within the command's database.
+        collection: String,
+        /// Any options the collection was created with, e.g. whether it is capped.
+        options: Document,
+    },
+    /// The deletion of a collection.
+    Drop {
+        /// The name of the collection within the command's database.
+        collection: String,
+    },
+    /// The deletion of the command's entire database.
+    DropDatabase,
+    /// The renaming of a collection, potentially into another database.
+    RenameCollection {
+        /// The full namespace of the collection being renamed.
+        from: String,
+        /// The full namespace the collection is renamed to.
+        to: String,
+        /// Whether any existing collection at the target namespace was dropped.
+        drop_target: bool,
+    },
+    /// The creation of one or more indexes on a collection.
+    CreateIndexes {
+        /// The name of the collection within the command's database.
+        collection: String,
+        /// The specifications of the indexes created including their keys and names.
+        indexes: Vec<Document>,
+    },
+    /// The deletion of an index from a collection.
+    DropIndexes {
+        /// The name of the collection within the command's database.
+        collection: String,
+        /// The name of the index dropped, `*` for every index or the key specification of the
+        /// index dropped.
+        index: Bson,
+    },
+    /// The modification of a collection's options.
+    CollMod {
+        /// The name of the collection within the command's database.
+        collection: String,
+        /// The options modified, e.g. a new validator or index expiry.
+        options: Document,
+    },
+    /// The conversion of a collection into a capped collection.
+    ConvertToCapped {
+        /// The name of the collection within the command's database.
+        collection: String,
+        /// The maximum size of the capped collection in bytes.
+        size: i64,
+    },
+    /// The removal of every document from a capped collection.
+    EmptyCapped {
+        /// The name of the collection within the command's database.
+        collection: String,
+    },
+    /// The commit of a prepared transaction.
+    CommitTransaction {
+        /// The time at which the transaction's writes become visible, if recorded.
+        commit_optime: Option<OpTime>,
+    },
+    /// The abort of a prepared transaction.
+    AbortTransaction,
+    /// Any other command as its original BSON document.
+    Other(Document),
+}
+
+impl CommandKind {
+    /// Try to create a new `CommandKind` from the BSON document of a command operation.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use]
+    /// # extern crate bson;
+    /// # extern crate oplog;
+    /// use oplog::CommandKind;
+    ///
+    /// # fn main() {
+    /// let command = CommandKind::new(&doc! { "drop" => "foo" }).unwrap();
+    ///
+    /// assert_eq!(command, CommandKind::Drop { collection: "foo".into() });
+    /// # }
+    /// ```
+    pub fn new(command: &Document) -> Result<CommandKind> {
+        let name = match command.keys().next() {
+            Some(name) => name.to_owned(),
+            None => return Ok(CommandKind::Other(command.to_owned())),
+        };
+
+        match name.as_str() {
+            "create" => {
+                Ok(CommandKind::Create {
+                    collection: command.get_str("create")?.into(),
+                    options: without(command, "create"),
+                })
+            }
+            "drop" => Ok(CommandKind::Drop { collection: command.get_str("drop")?.into() }),
+            "dropDatabase" => Ok(CommandKind::DropDatabase),
+            "renameCollection" => {
+                let drop_target = match command.get("dropTarget") {
+                    Some(&Bson::Boolean(drop_target)) => drop_target,
+                    Some(&Bson::Binary(..)) => true,
+                    _ => false,
+                };
+
+                Ok(CommandKind::RenameCollection {
+                    from: command.get_str("renameCollection")?.into(),
+                    to: command.get_str("to")?.into(),
+                    drop_target: drop_target,
+                })
+            }
+            "createIndexes" => {
+                let indexes = match command.get_array("indexes") {
+                    Ok(indexes) => {
+                        indexes.iter()
+                               .filter_map(|index| match *index {
+                                   Bson::Document(ref index) => Some(index.to_owned()),
+                                   _ => None,
+                               })
+                               .collect()
+                    }
+                    Err(_) => vec![without(command, "createIndexes")],
+                };
+
+                Ok(CommandKind::CreateIndexes {
+                    collection: command.get_str("createIndexes")?.into(),
+                    indexes: indexes,
+                })
+            }
+            "dropIndexes" | "deleteIndexes" => {
+                Ok(CommandKind::DropIndexes {
+                    collection: command.get_str(&name)?.into(),
+                    index: command.get("index").cloned().unwrap_or(Bson::Null),
+                })
+            }
+            "collMod" => {
+                Ok(CommandKind::CollMod {
+                    collection: command.get_str("collMod")?.into(),
+                    options: without(command, "collMod"),
+                })
+            }
+            "convertToCapped" => {
+                let size = match command.get("size") {
+                    Some(&Bson::I32(size)) => size as i64,
+                    Some(&Bson::I64(size)) => size,
+                    _ => command.get_f64("size")? as i64,
+                };
+
+                Ok(CommandKind::ConvertToCapped {
+                    collection: command.get_str("convertToCapped")?.into(),
+                    size: size,
+                })
+            }
+            "emptycapped" => {
+                Ok(CommandKind::EmptyCapped { collection: command.get_str("emptycapped")?.into() })
+            }
+            "commitTransaction" => {
+                let commit_optime = command.get_time_stamp("commitTimestamp")
+                                           .ok()
+                                           .map(|ts| OpTime::from_timestamp(ts, None));
+
+                Ok(CommandKind::CommitTransaction { commit_optime: commit_optime })
+            }
+            "abortTransaction" => Ok(CommandKind::AbortTransaction),
+            _ => Ok(CommandKind::Other(command.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for CommandKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CommandKind::Create { ref collection, ref options } => {
+                write!(f, "create {} with {}", collection, options)
+            }
+            CommandKind::Drop { ref collection } => write!(f, "drop {}", collection),
+            CommandKind::DropDatabase => write!(f, "dropDatabase"),
+            CommandKind::RenameCollection { ref from, ref to, .. } => {
+                write!(f, "renameCollection {} to {}", from, to)
+            }
+            CommandKind::CreateIndexes { ref collection, ref indexes } => {
+                write!(f, "createIndexes on {}: {} indexes", collection, indexes.len())
+            }
+            CommandKind::DropIndexes { ref collection, ref index } => {
+                write!(f, "dropIndexes {} on {}", index, collection)
+            }
+            CommandKind::CollMod { ref collection, ref options } => {
+                write!(f, "collMod {} with {}", collection, options)
+            }
+            CommandKind::ConvertToCapped { ref collection, size } => {
+                write!(f, "convertToCapped {} of {} bytes", collection, size)
+            }
+            CommandKind::EmptyCapped { ref collection } => write!(f, "emptycapped {}", collection),
+            CommandKind::CommitTransaction { .. } => write!(f, "commitTransaction"),
+            CommandKind::AbortTransaction => write!(f, "abortTransaction"),
+            CommandKind::Other(ref command) => write!(f, "{}", command),
+        }
+    }
+}
+
+/// Returns a copy of a command document without its named field.
+fn without(command: &Document, name: &str) -> Document {
+    let mut command = command.to_owned();
+    command.remove(name);
+
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use OpTime;
+    use bson::Bson;
+    use bson::spec::BinarySubtype;
+    use super::CommandKind;
+
+    #[test]
+    fn command_converts_creates() {
+        let command = CommandKind::new(&doc! { "create" => "foo", "capped" => true }).unwrap();
+
+        assert_eq!(command,
+                   CommandKind::Create {
+                       collection: "foo".into(),
+                       options: doc! { "capped" => true },
+                   });
+    }
+
+    #[test]
+    fn command_converts_drops() {
+        let command = CommandKind::new(&doc! { "drop" => "foo" }).unwrap();
+
+        assert_eq!(command, CommandKind::Drop { collection: "foo".into() });
+    }
+
+    #[test]
+    fn command_converts_drop_databases() {
+        let command = CommandKind::new(&doc! { "dropDatabase" => 1 }).unwrap();
+
+        assert_eq!(command, CommandKind::DropDatabase);
+    }
+
+    #[test]
+    fn command_converts_renames() {
+        let command = CommandKind::new(&doc! {
+                          "renameCollection" => "foo.bar",
+                          "to" => "baz.quux",
+                          "stayTemp" => false,
+                          "dropTarget" => (Bson::Binary(BinarySubtype::Uuid, vec![1, 2, 3]))
+                      })
+                          .unwrap();
+
+        assert_eq!(command,
+                   CommandKind::RenameCollection {
+                       from: "foo.bar".into(),
+                       to: "baz.quux".into(),
+                       drop_target: true,
+                   });
+    }
+
+    #[test]
+    fn command_converts_create_indexes() {
+        let command = CommandKind::new(&doc! {
+                          "createIndexes" => "foo",
+                          "v" => 2,
+                          "key" => { "bar" => 1 },
+                          "name" => "bar_1"
+                      })
+                          .unwrap();
+
+        assert_eq!(command,
+                   CommandKind::CreateIndexes {
+                       collection: "foo".into(),
+                       indexes: vec![doc! { "v" => 2, "key" => { "bar" => 1 }, "name" => "bar_1" }],
+                   });
+    }
+
+    #[test]
+    fn command_converts_create_indexes_with_multiple_indexes() {
+        let command = CommandKind::new(&doc! {
+                          "createIndexes" => "foo",
+                          "indexes" => [
+                              { "key" => { "bar" => 1 }, "name" => "bar_1" },
+                              { "key" => { "baz" => 1 }, "name" => "baz_1" }
+                          ]
+                      })
+                          .unwrap();
+
+        assert_eq!(command,
+                   CommandKind::CreateIndexes {
+                       collection: "foo".into(),
+                       indexes: vec![doc! { "key" => { "bar" => 1 }, "name" => "bar_1" },
+                                     doc! { "key" => { "baz" => 1 }, "name" => "baz_1" }],
+                   });
+    }
+
+    #[test]
+    fn command_converts_drop_indexes() {
+        let command = CommandKind::new(&doc! { "dropIndexes" => "foo", "index" => "bar_1" })
+                          .unwrap();
+
+        assert_eq!(command,
+                   CommandKind::DropIndexes {
+                       collection: "foo".into(),
+                       index: "bar_1".into(),
+                   });
+    }
+
+    #[test]
+    fn command_converts_drop_indexes_by_key() {
+        let command = CommandKind::new(&doc! {
+                          "dropIndexes" => "foo",
+                          "index" => { "bar" => 1 }
+                      })
+                          .unwrap();
+
+        assert_eq!(command,
+                   CommandKind::DropIndexes {
+                       collection: "foo".into(),
+                       index: Bson::Document(doc! { "bar" => 1 }),
+                   });
+    }
+
+    #[test]
+    fn command_converts_coll_mods() {
+        let command = CommandKind::new(&doc! {
+                          "collMod" => "foo",
+                          "validationLevel" => "moderate"
+                      })
+                          .unwrap();
+
+        assert_eq!(command,
+                   CommandKind::CollMod {
+                       collection: "foo".into(),
+                       options: doc! { "validationLevel" => "moderate" },
+                   });
+    }
+
+    #[test]
+    fn command_converts_convert_to_capped() {
+        let command = CommandKind::new(&doc! { "convertToCapped" => "foo", "size" => 4096.0 })
+                          .unwrap();
+
+        assert_eq!(command,
+                   CommandKind::ConvertToCapped {
+                       collection: "foo".into(),
+                       size: 4096,
+                   });
+    }
+
+    #[test]
+    fn command_converts_empty_capped() {
+        let command = CommandKind::new(&doc! { "emptycapped" => "foo" }).unwrap();
+
+        assert_eq!(command, CommandKind::EmptyCapped { collection: "foo".into() });
+    }
+
+    #[test]
+    fn command_converts_commit_transactions() {
+        let command = CommandKind::new(&doc! {
+                          "commitTransaction" => 1,
+                          "commitTimestamp" => (Bson::TimeStamp(1483789052 << 32 | 1))
+                      })
+                          .unwrap();
+
+        assert_eq!(command,
+                   CommandKind::CommitTransaction {
+                       commit_optime: Some(OpTime::new(1483789052, 1, None)),
+                   });
+    }
+
+    #[test]
+    fn command_preserves_unknown_commands() {
+        let command = CommandKind::new(&doc! { "startIndexBuild" => "foo" }).unwrap();
+
+        assert_eq!(command, CommandKind::Other(doc! { "startIndexBuild" => "foo" }));
+    }
+}
@@ -0,0 +1,291 @@
+//! The cursor module is responsible for reading the oplog with the `find` and `getMore` commands
+//! rather than the driver's own cursor so that a cursor closed by the server can be detected.
+//!
+//! The driver discards the cursor id returned by each `getMore` and silently returns no more
+//! documents once the server has closed its cursor, e.g. after an election or when a tailable query
+//! initially matches nothing, which is indistinguishable from a quiet oplog.
+//!
+//! Every command of a cursor is sent over the same connection as a cursor only exists on the
+//! member of the replica set that answered its `find`.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use bson::{Bson, Document};
+use mongodb::{self, Client, CommandType, ThreadedClient};
+use mongodb::coll::options::FindOptions;
+use mongodb::common::{ReadMode, ReadPreference};
+use mongodb::pool::PooledStream;
+use mongodb::wire_protocol::flags::OpQueryFlags;
+
+use Result;
+
+/// The collection holding the oplog within the `local` database.
+pub(crate) const COLLECTION: &str = "oplog.rs";
+
+/// A cursor over the oplog.
+pub(crate) struct Cursor {
+    /// The MongoDB client used to fetch each batch.
+    client: Client,
+    /// The connection to the member of the replica set holding the cursor.
+    stream: PooledStream,
+    /// The flags sent with each command, e.g. to allow reading from a secondary.
+    flags: OpQueryFlags,
+    /// The read preference to forward with each command when connected to a `mongos`, if any.
+    read_preference: Option<ReadPreference>,
+    /// The number of documents to fetch in each batch, if not the server's default.
+    batch_size: Option<i32>,
+    /// How long the server should await new documents for each batch of a tailable cursor, if
+    /// not its default of one second.
+    max_await_time: Option<Duration>,
+    /// The server's id for the cursor or 0 once it has been closed.
+    id: i64,
+    /// The documents fetched but yet to be returned.
+    buffer: VecDeque<Document>,
+}
+
+impl Cursor {
+    /// Returns a new cursor over the results of the given `find` command, read from a member of
+    /// the replica set selected by the given read preference or the client's default.
+    pub(crate) fn new(client: &Client,
+                      command: Document,
+                      batch_size: Option<i32>,
+                      max_await_time: Option<Duration>,
+                      read_preference: Option<ReadPreference>)
+                      -> Result<Cursor> {
+        let read_preference = read_preference.unwrap_or_else(|| client.read_preference.clone());
+        let (stream, slave_ok, send_read_preference) =
+            client.acquire_stream(read_preference.clone())?;
+
+        let mut cursor = Cursor {
+            client: client.clone(),
+            stream: stream,
+            flags: if slave_ok { OpQueryFlags::SLAVE_OK } else { OpQueryFlags::empty() },
+            read_preference: if send_read_preference { Some(read_preference) } else { None },
+            batch_size: batch_size,
+            max_await_time: max_await_time,
+            id: 0,
+            buffer: VecDeque::new(),
+        };
+
+        let reply = cursor.run(command, CommandType::Find)?;
+        let (id, documents) = batch(&reply, "firstBatch")?;
+        cursor.id = id;
+        cursor.buffer.extend(documents);
+
+        Ok(cursor)
+    }
+
+    /// Returns whether the server has closed the cursor and every document has been returned.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.id == 0 && self.buffer.is_empty()
+    }
+
+    /// Fetch the next batch of documents, awaiting new ones if the cursor is tailable.
+    fn get_more(&mut self) -> Result<()> {
+        let command = get_more_command(self.id, self.batch_size, self.max_await_time);
+        let reply = self.run(command, CommandType::Suppressed)?;
+        let (id, documents) = batch(&reply, "nextBatch")?;
+        self.id = id;
+        self.buffer.extend(documents);
+
+        Ok(())
+    }
+
+    /// Returns the reply to running the given command against the `local` database of the member
+    /// of the replica set holding the cursor.
+    fn run(&mut self, command: Document, command_type: CommandType) -> Result<Document> {
+        let command = match self.read_preference {
+            Some(ref read_preference) => {
+                doc! {
+                    "$query" => command,
+                    "$readPreference" => (read_preference_document(read_preference))
+                }
+            }
+            None => command,
+        };
+        let mut options = FindOptions::new();
+        options.batch_size = Some(1);
+
+        let mut replies = mongodb::cursor::Cursor::query_with_stream(&mut self.stream,
+                                                                     self.client.clone(),
+                                                                     "local.$cmd".to_owned(),
+                                                                     self.flags,
+                                                                     command,
+                                                                     options,
+                                                                     command_type,
+                                                                     false,
+                                                                     None)?;
+
+        match replies.next() {
+            Some(reply) => Ok(reply?),
+            None => Err(mongodb::Error::OperationError("Command returned no reply".into()).into()),
+        }
+    }
+}
+
+impl Iterator for Cursor {
+    type Item = Result<Document>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() && self.id != 0 {
+            if let Err(err) = self.get_more() {
+                return Some(Err(err));
+            }
+        }
+
+        self.buffer.pop_front().map(Ok)
+    }
+}
+
+/// Returns the `getMore` command fetching the next batch of the given cursor.
+fn get_more_command(id: i64,
+                    batch_size: Option<i32>,
+                    max_await_time: Option<Duration>)
+                    -> Document {
+    let mut command = doc! { "getMore" => id, "collection" => COLLECTION };
+
+    if let Some(batch_size) = batch_size {
+        command.insert("batchSize", batch_size);
+    }
+
+    if let Some(max_await_time) = max_await_time {
+        let millis = max_await_time.as_secs() * 1000 + u64::from(max_await_time.subsec_millis());
+        command.insert("maxTimeMS", millis.min(i64::MAX as u64) as i64);
+    }
+
+    command
+}
+
+/// Returns the given read preference as sent to a `mongos` in `$readPreference`.
+fn read_preference_document(read_preference: &ReadPreference) -> Document {
+    let mode = match read_preference.mode {
+        ReadMode::Primary => "primary",
+        ReadMode::PrimaryPreferred => "primaryPreferred",
+        ReadMode::Secondary => "secondary",
+        ReadMode::SecondaryPreferred => "secondaryPreferred",
+        ReadMode::Nearest => "nearest",
+    };
+    let mut document = doc! { "mode" => mode };
+
+    if !read_preference.tag_sets.is_empty() {
+        let tag_sets = read_preference.tag_sets
+                                      .iter()
+                                      .map(|tags| {
+                                          let mut tag_set = Document::new();
+
+                                          for (key, value) in tags {
+                                              tag_set.insert(key.to_owned(), value.to_owned());
+                                          }
+
+                                          Bson::Document(tag_set)
+                                      })
+                                      .collect();
+        document.insert("tags", Bson::Array(tag_sets));
+    }
+
+    document
+}
+
+/// Returns the cursor id and the documents in the given field of the reply to a `find` or
+/// `getMore` command.
+fn batch(reply: &Document, field: &str) -> Result<(i64, Vec<Document>)> {
+    if let Some(&Bson::String(ref message)) = reply.get("errmsg") {
+        return Err(mongodb::Error::OperationError(message.to_owned()).into());
+    }
+
+    let cursor = reply.get_document("cursor")?;
+    let id = match cursor.get("id") {
+        Some(&Bson::I32(id)) => id as i64,
+        _ => cursor.get_i64("id")?,
+    };
+    let documents = cursor.get_array(field)?
+                          .iter()
+                          .filter_map(|document| match *document {
+                              Bson::Document(ref document) => Some(document.to_owned()),
+                              _ => None,
+                          })
+                          .collect();
+
+    Ok((id, documents))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    use mongodb::common::{ReadMode, ReadPreference};
+    use Error;
+    use super::{batch, get_more_command, read_preference_document};
+
+    #[test]
+    fn batch_reads_the_cursor_id_and_documents() {
+        let reply = doc! {
+            "cursor" => {
+                "firstBatch" => [{ "op" => "n" }, { "op" => "i" }],
+                "id" => 42i64,
+                "ns" => "local.oplog.rs"
+            },
+            "ok" => 1.0
+        };
+
+        assert_eq!(batch(&reply, "firstBatch").unwrap(),
+                   (42, vec![doc! { "op" => "n" }, doc! { "op" => "i" }]));
+    }
+
+    #[test]
+    fn batch_reads_closed_cursors() {
+        let reply = doc! {
+            "cursor" => { "nextBatch" => [], "id" => 0i64, "ns" => "local.oplog.rs" },
+            "ok" => 1.0
+        };
+
+        assert_eq!(batch(&reply, "nextBatch").unwrap(), (0, vec![]));
+    }
+
+    #[test]
+    fn batch_returns_command_errors() {
+        let reply = doc! { "ok" => 0.0, "errmsg" => "cursor id 42 not found", "code" => 43 };
+
+        match batch(&reply, "nextBatch") {
+            Err(Error::Database(_)) => {}
+            _ => panic!("Expected database error."),
+        }
+    }
+
+    #[test]
+    fn get_more_command_fetches_the_next_batch() {
+        assert_eq!(get_more_command(42, None, None),
+                   doc! { "getMore" => 42i64, "collection" => "oplog.rs" });
+    }
+
+    #[test]
+    fn get_more_command_sets_the_batch_size_and_maximum_await_time() {
+        assert_eq!(get_more_command(42, Some(1000), Some(Duration::from_millis(5500))),
+                   doc! {
+                       "getMore" => 42i64,
+                       "collection" => "oplog.rs",
+                       "batchSize" => 1000,
+                       "maxTimeMS" => 5500i64
+                   });
+    }
+
+    #[test]
+    fn read_preference_document_names_the_mode() {
+        let read_preference = ReadPreference::new(ReadMode::SecondaryPreferred, None);
+
+        assert_eq!(read_preference_document(&read_preference),
+                   doc! { "mode" => "secondaryPreferred" });
+    }
+
+    #[test]
+    fn read_preference_document_includes_tag_sets() {
+        let mut tags = BTreeMap::new();
+        tags.insert("dc".to_owned(), "east".to_owned());
+        let read_preference = ReadPreference::new(ReadMode::Secondary, Some(vec![tags]));
+
+        assert_eq!(read_preference_document(&read_preference),
+                   doc! { "mode" => "secondary", "tags" => [{ "dc" => "east" }] });
+    }
+}
@@ -0,0 +1,170 @@
+//! The event module is responsible for iterating over an `Oplog` while regularly handing control
+//! back to the caller when it is idle, e.g. to flush buffers, store checkpoints or report
+//! liveness.
+
+use std::time::{Duration, Instant};
+
+use {Operation, Oplog, OplogSource, OpTime, Result};
+use oplog::Step;
+
+/// An event while reading an `Oplog`.
+///
+/// The operation is boxed as it is far larger than an idle event.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OplogEvent {
+    /// An operation was read.
+    Operation(Box<Operation>),
+    /// No operation was read within the idle timeout.
+    Idle {
+        /// The position of the last entry read from the oplog, including any filtered out, if
+        /// any.
+        last_seen: Option<OpTime>,
+    },
+}
+
+/// An iterator over an `Oplog` yielding the result of reading each operation as an event and an
+/// idle event whenever no operation is read within its idle timeout.
+///
+/// The oplog is only checked for new operations between reads from its source so idle events may
+/// be delayed by however long the source blocks, e.g. the server's await time while tailing.
+///
+/// This is created by `Oplog::events`.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate mongodb;
+/// # extern crate oplog;
+/// use std::time::Duration;
+///
+/// use mongodb::{Client, ThreadedClient};
+/// use oplog::{OplogBuilder, OplogEvent};
+///
+/// # fn main() {
+/// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+/// let mut oplog = OplogBuilder::new(&client)
+///     .idle_timeout(Duration::from_secs(5))
+///     .build()
+///     .expect("Failed to read oplog.");
+///
+/// for event in oplog.events() {
+///     match event {
+///         Ok(OplogEvent::Operation(operation)) => {
+///             // Do something with operation...
+///         }
+///         Ok(OplogEvent::Idle { last_seen }) => {
+///             // Flush any buffered work...
+///         }
+///         Err(err) => println!("Failed to read oplog: {}", err),
+///     }
+/// }
+/// # }
+/// ```
+pub struct OplogEvents<'a, S: 'a> {
+    oplog: &'a mut Oplog<S>,
+    timeout: Option<Duration>,
+    active: Instant,
+}
+
+impl<'a, S: OplogSource> OplogEvents<'a, S> {
+    /// Returns a new iterator over the given oplog with the given idle timeout, if any.
+    pub(crate) fn new(oplog: &'a mut Oplog<S>, timeout: Option<Duration>) -> OplogEvents<'a, S> {
+        OplogEvents {
+            oplog: oplog,
+            timeout: timeout,
+            active: Instant::now(),
+        }
+    }
+
+    /// Returns whether no event has been yielded within the idle timeout.
+    fn is_idle(&self) -> bool {
+        match self.timeout {
+            Some(timeout) => self.active.elapsed() >= timeout,
+            None => false,
+        }
+    }
+}
+
+impl<'a, S: OplogSource> Iterator for OplogEvents<'a, S> {
+    type Item = Result<OplogEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.oplog.step() {
+                Step::Entry(entry) => {
+                    self.active = Instant::now();
+
+                    return Some((*entry).map(|(operation, _)| {
+                        OplogEvent::Operation(Box::new(operation))
+                    }));
+                }
+                Step::Pending if self.is_idle() => {
+                    self.active = Instant::now();
+
+                    return Some(Ok(OplogEvent::Idle { last_seen: self.oplog.last_optime() }));
+                }
+                Step::Pending => continue,
+                Step::End => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bson::{Bson, Document};
+    use {OpTime, Oplog, OplogSource, Result};
+    use super::{OplogEvent, OplogEvents};
+
+    /// A tailing source yielding a single document and then awaiting more forever.
+    struct QuietSource {
+        document: Option<Document>,
+    }
+
+    impl OplogSource for QuietSource {
+        fn next_document(&mut self) -> Option<Result<Document>> {
+            self.document.take().map(Ok)
+        }
+
+        fn is_tailing(&self) -> bool {
+            true
+        }
+    }
+
+    fn quiet() -> Oplog<QuietSource> {
+        let document = doc! {
+            "ts" => (Bson::TimeStamp(1479561394 << 32 | 1)),
+            "v" => 2,
+            "op" => "n",
+            "ns" => "",
+            "o" => { "msg" => "periodic noop" }
+        };
+
+        Oplog::from_source(QuietSource { document: Some(document) })
+    }
+
+    #[test]
+    fn events_yield_operations_then_idle_events() {
+        let mut oplog = quiet();
+        let mut events = OplogEvents::new(&mut oplog, Some(Duration::from_millis(10)));
+
+        match events.next() {
+            Some(Ok(OplogEvent::Operation(_))) => {}
+            _ => panic!("Expected operation."),
+        }
+        assert_eq!(events.next().unwrap().unwrap(),
+                   OplogEvent::Idle { last_seen: Some(OpTime::new(1479561394, 1, None)) });
+        assert_eq!(events.next().unwrap().unwrap(),
+                   OplogEvent::Idle { last_seen: Some(OpTime::new(1479561394, 1, None)) });
+    }
+
+    #[test]
+    fn events_end_with_the_oplog() {
+        let mut oplog = Oplog::from_source(Vec::new().into_iter());
+        let mut events = OplogEvents::new(&mut oplog, Some(Duration::from_millis(10)));
+
+        assert!(events.next().is_none());
+    }
+}
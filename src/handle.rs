@@ -0,0 +1,229 @@
+//! The handle module is responsible for tailing an `Oplog` on a background thread and handing each
+//! operation to the caller over a bounded channel.
+//!
+//! The channel's capacity limits how far the thread can read ahead of the caller so that a slow
+//! consumer applies backpressure rather than buffering an unbounded number of operations.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use {Operation, Oplog, OplogSource, Result};
+use oplog::Step;
+
+/// A handle to an `Oplog` being tailed on a background thread.
+///
+/// It implements the `Iterator` trait, yielding the result of reading each operation until the
+/// oplog ends or is shut down. Use `receiver` for non-blocking or timed receives instead.
+///
+/// Dropping the handle shuts the thread down without waiting for it to finish.
+///
+/// This is created by `Oplog::spawn` or `OplogBuilder::spawn`.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate mongodb;
+/// # extern crate oplog;
+/// use mongodb::{Client, ThreadedClient};
+/// use oplog::OplogBuilder;
+///
+/// # fn main() {
+/// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+///
+/// if let Ok(handle) = OplogBuilder::new(&client).spawn(100) {
+///     for result in handle.receiver().iter().take(10) {
+///         // Do something with result...
+///     }
+///
+///     handle.join().expect("Failed to stop tailing the oplog.");
+/// }
+/// # }
+/// ```
+pub struct OplogHandle {
+    /// The receiving end of the channel of operations.
+    receiver: Receiver<Result<Operation>>,
+    /// Whether the thread has been asked to stop.
+    shutdown: Arc<AtomicBool>,
+    /// The thread tailing the oplog.
+    thread: Option<JoinHandle<()>>,
+}
+
+impl OplogHandle {
+    /// Returns a new handle tailing the given oplog on a new thread, buffering up to the given
+    /// number of operations.
+    pub(crate) fn spawn<S>(oplog: Oplog<S>, capacity: usize) -> OplogHandle
+        where S: OplogSource + Send + 'static
+    {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let sender = HandleSender {
+            sender: sender,
+            shutdown: shutdown.clone(),
+        };
+
+        OplogHandle {
+            receiver: receiver,
+            shutdown: shutdown,
+            thread: Some(drive(oplog, sender)),
+        }
+    }
+
+    /// Returns the receiving end of the channel of operations.
+    pub fn receiver(&self) -> &Receiver<Result<Operation>> {
+        &self.receiver
+    }
+
+    /// Ask the thread to stop tailing the oplog once it has handed over the operation it is
+    /// reading, if any.
+    ///
+    /// Operations already buffered can still be received, after which the channel ends.
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+    }
+
+    /// Shut the thread down, discarding any buffered operations, and wait for it to finish.
+    ///
+    /// Returns an error if the thread panicked.
+    pub fn join(mut self) -> thread::Result<()> {
+        self.shutdown();
+        let thread = self.thread.take();
+        drop(self);
+
+        match thread {
+            Some(thread) => thread.join(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Iterator for OplogHandle {
+    type Item = Result<Operation>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.recv().ok()
+    }
+}
+
+impl Drop for OplogHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// The sending end of a channel of operations read on a background thread by `drive`.
+pub(crate) trait OperationSender {
+    /// Returns whether the receiving end has stopped accepting operations.
+    fn is_closed(&self) -> bool;
+
+    /// Send the given result, blocking until there is room for it, and return whether it was
+    /// accepted.
+    fn send(&mut self, result: Result<Operation>) -> bool;
+}
+
+/// The sending end of the channel of an `OplogHandle`.
+struct HandleSender {
+    sender: SyncSender<Result<Operation>>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl OperationSender for HandleSender {
+    fn is_closed(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    fn send(&mut self, result: Result<Operation>) -> bool {
+        self.sender.send(result).is_ok()
+    }
+}
+
+/// Read the given oplog on a new thread, handing the result of reading each operation to the given
+/// sender until the oplog ends or the sender is closed.
+pub(crate) fn drive<S, T>(mut oplog: Oplog<S>, mut sender: T) -> JoinHandle<()>
+    where S: OplogSource + Send + 'static,
+          T: OperationSender + Send + 'static
+{
+    thread::spawn(move || {
+        while !sender.is_closed() {
+            let entry = match oplog.step() {
+                Step::Entry(entry) => *entry,
+                Step::Pending => continue,
+                Step::End => break,
+            };
+
+            if !sender.send(entry.map(|(operation, _)| operation)) {
+                break;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use bson::{Bson, Document};
+    use {Oplog, OplogSource, Result};
+
+    fn insert(seconds: i64) -> Document {
+        doc! {
+            "ts" => (Bson::TimeStamp(seconds << 32)),
+            "v" => 2,
+            "op" => "i",
+            "ns" => "foo.bar",
+            "o" => { "_id" => seconds }
+        }
+    }
+
+    /// A tailing source yielding an endless series of inserts and counting how many it has read.
+    struct EndlessSource {
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl OplogSource for EndlessSource {
+        fn next_document(&mut self) -> Option<Result<Document>> {
+            let reads = self.reads.fetch_add(1, Ordering::SeqCst) + 1;
+
+            Some(Ok(insert(reads as i64)))
+        }
+
+        fn is_tailing(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn oplog_handle_yields_operations() {
+        let handle = Oplog::from_source(vec![insert(1), insert(2)].into_iter()).spawn(1);
+        let results: Vec<_> = handle.collect();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].as_ref().unwrap().optime().seconds, 2);
+    }
+
+    #[test]
+    fn oplog_handle_applies_backpressure() {
+        let reads = Arc::new(AtomicUsize::new(0));
+        let handle = Oplog::from_source(EndlessSource { reads: reads.clone() }).spawn(1);
+
+        thread::sleep(Duration::from_millis(50));
+
+        assert!(reads.load(Ordering::SeqCst) <= 2);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn oplog_handle_drains_after_shutdown() {
+        let reads = Arc::new(AtomicUsize::new(0));
+        let handle = Oplog::from_source(EndlessSource { reads: reads.clone() }).spawn(2);
+
+        thread::sleep(Duration::from_millis(50));
+        handle.shutdown();
+
+        assert!(handle.count() <= 3);
+    }
+}
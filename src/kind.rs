@@ -0,0 +1,185 @@
+//! The kind module is responsible for classifying operations by their type so that users can
+//! restrict the operations read from the oplog to only those they are interested in.
+
+use bson::Document;
+
+use Operation;
+
+/// The kind of an `Operation`, e.g. an insert or update.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OperationKind {
+    /// An `Operation::Noop`.
+    Noop,
+    /// An `Operation::Insert`.
+    Insert,
+    /// An `Operation::Update`.
+    Update,
+    /// An `Operation::Delete`.
+    Delete,
+    /// An `Operation::Command`.
+    Command,
+    /// An `Operation::ApplyOps`.
+    ApplyOps,
+    /// An `Operation::Transaction`.
+    Transaction,
+    /// An `Operation::Unknown`.
+    Unknown,
+}
+
+impl OperationKind {
+    /// Returns the `op` field of oplog entries of this kind, if known.
+    fn code(&self) -> Option<&'static str> {
+        match *self {
+            OperationKind::Noop => Some("n"),
+            OperationKind::Insert => Some("i"),
+            OperationKind::Update => Some("u"),
+            OperationKind::Delete => Some("d"),
+            OperationKind::Command |
+            OperationKind::ApplyOps |
+            OperationKind::Transaction => Some("c"),
+            OperationKind::Unknown => None,
+        }
+    }
+}
+
+/// The kinds of operation to read from the oplog or every kind if empty.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Kinds {
+    kinds: Vec<OperationKind>,
+}
+
+impl Kinds {
+    /// Returns new `Kinds` for the given kinds of operation.
+    pub fn new(kinds: &[OperationKind]) -> Kinds {
+        Kinds { kinds: kinds.to_vec() }
+    }
+
+    /// Returns the clauses to add to the server-side query for these kinds.
+    ///
+    /// Commands are always read as they may contain operations of other kinds, e.g. an
+    /// `applyOps`. Nothing can be filtered on the server if unknown operations are wanted.
+    pub fn clauses(&self) -> Vec<Document> {
+        if self.kinds.is_empty() {
+            return Vec::new();
+        }
+
+        let mut codes = vec!["c"];
+
+        for kind in &self.kinds {
+            match kind.code() {
+                Some(code) if !codes.contains(&code) => codes.push(code),
+                Some(_) => {}
+                None => return Vec::new(),
+            }
+        }
+
+        vec![doc! { "op" => { "$in" => (codes.into_iter().map(Into::into).collect::<Vec<_>>()) } }]
+    }
+
+    /// Returns the given operation without any nested operations of other kinds or `None` if it
+    /// is entirely of other kinds.
+    ///
+    /// The entries needed to reassemble transactions are kept regardless when keeping
+    /// transactions.
+    pub fn retain(&self, operation: Operation, transactions: bool) -> Option<Operation> {
+        if self.kinds.is_empty() {
+            return Some(operation);
+        }
+
+        operation.retain(&|operation| self.includes(operation), transactions)
+    }
+
+    /// Returns whether the given operation, ignoring any nested operations, is included.
+    fn includes(&self, operation: &Operation) -> bool {
+        self.kinds.contains(&operation.kind())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{Bson, Document};
+    use Operation;
+    use super::{Kinds, OperationKind};
+
+    fn operation(op: &str, namespace: &str, o: Document) -> Operation {
+        Operation::new(&doc! {
+            "ts" => (Bson::TimeStamp(1479561394 << 32)),
+            "v" => 2,
+            "op" => op,
+            "ns" => namespace,
+            "o" => o
+        })
+            .unwrap()
+    }
+
+    #[test]
+    fn operation_returns_its_kind() {
+        assert_eq!(operation("i", "foo.bar", doc! {}).kind(), OperationKind::Insert);
+        assert_eq!(operation("c", "foo.$cmd", doc! { "drop" => "bar" }).kind(),
+                   OperationKind::Command);
+    }
+
+    #[test]
+    fn kinds_compose_server_side_clauses() {
+        let kinds = Kinds::new(&[OperationKind::Insert, OperationKind::Delete]);
+
+        assert_eq!(kinds.clauses(), vec![doc! { "op" => { "$in" => ["c", "i", "d"] } }]);
+    }
+
+    #[test]
+    fn kinds_are_unrestricted_by_default() {
+        assert_eq!(Kinds::default().clauses(), vec![]);
+    }
+
+    #[test]
+    fn kinds_are_unrestricted_on_the_server_for_unknown_operations() {
+        let kinds = Kinds::new(&[OperationKind::Insert, OperationKind::Unknown]);
+
+        assert_eq!(kinds.clauses(), vec![]);
+    }
+
+    #[test]
+    fn kinds_retain_matching_operations() {
+        let kinds = Kinds::new(&[OperationKind::Insert]);
+
+        let drop = operation("c", "foo.$cmd", doc! { "drop" => "bar" });
+
+        assert!(kinds.retain(operation("i", "foo.bar", doc! {}), false).is_some());
+        assert!(kinds.retain(drop, false).is_none());
+    }
+
+    #[test]
+    fn kinds_retain_nested_operations() {
+        let kinds = Kinds::new(&[OperationKind::Insert]);
+        let apply_ops = operation("c",
+                                  "admin.$cmd",
+                                  doc! {
+                                      "applyOps" => [
+                                          { "op" => "i", "ns" => "foo.bar", "o" => {} },
+                                          { "op" => "d", "ns" => "foo.bar", "o" => {} }
+                                      ]
+                                  });
+
+        match kinds.retain(apply_ops, false) {
+            Some(Operation::ApplyOps { operations, .. }) => {
+                assert_eq!(operations.len(), 1);
+                assert_eq!(operations[0].kind(), OperationKind::Insert);
+            }
+            _ => panic!("Expected apply ops."),
+        }
+    }
+
+    #[test]
+    fn kinds_retain_whole_apply_ops() {
+        let kinds = Kinds::new(&[OperationKind::ApplyOps]);
+        let apply_ops = operation("c",
+                                  "admin.$cmd",
+                                  doc! {
+                                      "applyOps" => [
+                                          { "op" => "d", "ns" => "foo.bar", "o" => {} }
+                                      ]
+                                  });
+
+        assert_eq!(kinds.retain(apply_ops.clone(), false), Some(apply_ops));
+    }
+}
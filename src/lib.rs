@@ -52,16 +52,59 @@
 extern crate bson;
 extern crate mongodb;
 extern crate chrono;
+extern crate regex;
+#[cfg(feature = "gzip")]
+extern crate flate2;
+#[cfg(feature = "zstd")]
+extern crate zstd;
+#[cfg(feature = "async")]
+extern crate futures;
 
 use std::error;
 use std::fmt;
+use std::io;
 use std::result;
 
+pub use archive::{Archiver, ArchiveSegment, ArchiveSource, Compression, Rotation};
+pub use backoff::Backoff;
+pub use checkpoint::{Checkpoint, FileCheckpoint, MemoryCheckpoint};
+pub use command::CommandKind;
+pub use event::{OplogEvent, OplogEvents};
+pub use handle::OplogHandle;
+pub use kind::OperationKind;
+pub use merged::{MergedOplog, TryMergedOplog};
+pub use meta::OperationMeta;
 pub use operation::Operation;
-pub use oplog::{Oplog, OplogBuilder};
+pub use oplog::{Oplog, OplogBuilder, TryOplog};
+pub use optime::OpTime;
+pub use source::{BsonSource, CursorSource, OplogSource};
+#[cfg(feature = "async")]
+pub use stream::OplogStream;
+pub use transaction::Transactions;
+pub use update::{TruncatedArray, UpdateDescription};
 
+mod archive;
+mod backoff;
+mod checkpoint;
+mod command;
+mod cursor;
+mod event;
+mod handle;
+mod kind;
+mod majority;
+mod matcher;
+mod merged;
+mod meta;
+mod namespace;
 mod operation;
 mod oplog;
+mod optime;
+mod query;
+mod source;
+#[cfg(feature = "async")]
+mod stream;
+mod transaction;
+mod update;
 
 /// A type alias for convenience so we can fix the error to our own `Error` type.
 pub type Result<T> = result::Result<T, Error>;
@@ -79,6 +122,44 @@ pub enum Error {
     UnknownOperation(String),
     /// An error when converting an applyOps command with invalid documents.
     InvalidOperation,
+    /// An error when building an `Oplog` with an invalid namespace regular expression.
+    InvalidNamespace(regex::Error),
+    /// An I/O error when reading oplog documents from an `OplogSource`.
+    Io(io::Error),
+    /// An error when decoding a BSON document read from an `OplogSource`.
+    Decode(bson::DecoderError),
+    /// An error when encoding a BSON document written to an archive.
+    Encode(bson::EncoderError),
+    /// An error when building an `Oplog` with a filter from a source other than the server, which
+    /// cannot apply it.
+    UnsupportedFilter,
+    /// An error when the replica set refuses to report its majority-committed position to an
+    /// `Oplog` reading only majority-committed operations, e.g. as the user lacks the
+    /// `clusterMonitor` role required to run `replSetGetStatus`.
+    ReplicaSetStatus(String),
+    /// An error when the server closes the cursor tailing the oplog, e.g. after an election or as
+    /// its query initially matched nothing.
+    CursorClosed,
+    /// An error when reading the oplog from a position that has since been truncated from it.
+    ///
+    /// As operations may have been lost, consumers should typically resynchronise in full.
+    OplogRolledOver {
+        /// The requested position.
+        requested: OpTime,
+        /// The oldest position remaining in the oplog.
+        oldest: OpTime,
+    },
+    /// An error when resuming the oplog after an operation that has since been rolled back, e.g.
+    /// after an election.
+    ///
+    /// Any operations read after the `common` position up to and including `last` may no longer
+    /// exist.
+    Rollback {
+        /// The newest position before `last` that remains in the oplog, if any.
+        common: Option<OpTime>,
+        /// The position of the last operation read.
+        last: OpTime,
+    },
 }
 
 impl error::Error for Error {
@@ -88,6 +169,15 @@ impl error::Error for Error {
             Error::MissingField(ref err) => err.description(),
             Error::UnknownOperation(_) => "unknown operation type",
             Error::InvalidOperation => "invalid operation",
+            Error::InvalidNamespace(ref err) => err.description(),
+            Error::Io(ref err) => err.description(),
+            Error::Decode(ref err) => err.description(),
+            Error::Encode(ref err) => err.description(),
+            Error::UnsupportedFilter => "unsupported filter",
+            Error::ReplicaSetStatus(_) => "replica set status unavailable",
+            Error::CursorClosed => "cursor closed",
+            Error::OplogRolledOver { .. } => "oplog rolled over",
+            Error::Rollback { .. } => "operations rolled back",
         }
     }
 }
@@ -99,6 +189,26 @@ impl fmt::Display for Error {
             Error::MissingField(ref err) => err.fmt(f),
             Error::UnknownOperation(ref op) => write!(f, "Unknown operation type found: {}", op),
             Error::InvalidOperation => write!(f, "Invalid operation"),
+            Error::InvalidNamespace(ref err) => write!(f, "Invalid namespace: {}", err),
+            Error::Io(ref err) => err.fmt(f),
+            Error::Decode(ref err) => err.fmt(f),
+            Error::Encode(ref err) => err.fmt(f),
+            Error::UnsupportedFilter => {
+                write!(f, "Filters can only be applied when reading from the server")
+            }
+            Error::ReplicaSetStatus(ref message) => {
+                write!(f, "Replica set status unavailable: {}", message)
+            }
+            Error::CursorClosed => write!(f, "Cursor closed by the server"),
+            Error::OplogRolledOver { requested, oldest } => {
+                write!(f, "Oplog rolled over: requested {} but oldest is {}", requested, oldest)
+            }
+            Error::Rollback { common: Some(common), last } => {
+                write!(f, "Operations after {} up to {} rolled back", common, last)
+            }
+            Error::Rollback { common: None, last } => {
+                write!(f, "Operations up to {} rolled back", last)
+            }
         }
     }
 }
@@ -109,6 +219,30 @@ impl From<bson::ValueAccessError> for Error {
     }
 }
 
+impl From<regex::Error> for Error {
+    fn from(original: regex::Error) -> Error {
+        Error::InvalidNamespace(original)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(original: io::Error) -> Error {
+        Error::Io(original)
+    }
+}
+
+impl From<bson::DecoderError> for Error {
+    fn from(original: bson::DecoderError) -> Error {
+        Error::Decode(original)
+    }
+}
+
+impl From<bson::EncoderError> for Error {
+    fn from(original: bson::EncoderError) -> Error {
+        Error::Encode(original)
+    }
+}
+
 impl From<mongodb::Error> for Error {
     fn from(original: mongodb::Error) -> Error {
         Error::Database(original)
@@ -0,0 +1,358 @@
+//! The majority module is responsible for holding back oplog documents until they have been
+//! committed to a majority of the replica set so that they can never be rolled back.
+//!
+//! The majority-committed position is read through the `CommittedPosition` trait so that the
+//! gating can be exercised without a live replica set.
+
+use std::thread;
+use std::time::Duration;
+
+use bson::{Bson, Document};
+use mongodb::{self, Client, CommandType, ThreadedClient};
+use mongodb::db::ThreadedDatabase;
+
+use {Error, OpTime, Result};
+
+/// How long to wait before polling the replica set again for a newly majority-committed position.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A provider of the majority-committed position of a replica set.
+pub(crate) trait CommittedPosition {
+    /// Returns the latest majority-committed position.
+    fn committed(&mut self) -> Result<OpTime>;
+}
+
+/// The majority-committed position as reported by `replSetGetStatus`.
+///
+/// Running `replSetGetStatus` requires the `clusterMonitor` role (or another granting the
+/// `replSetGetStatus` action) so a user without it receives an `Error::ReplicaSetStatus`.
+pub(crate) struct ReplicaSetStatus {
+    /// The MongoDB client used to poll the replica set.
+    client: Client,
+}
+
+impl ReplicaSetStatus {
+    /// Returns a new provider polling the replica set with the given MongoDB client.
+    pub(crate) fn new(client: &Client) -> ReplicaSetStatus {
+        ReplicaSetStatus { client: client.clone() }
+    }
+}
+
+impl CommittedPosition for ReplicaSetStatus {
+    fn committed(&mut self) -> Result<OpTime> {
+        let status = self.client
+                         .db("admin")
+                         .command(doc! { "replSetGetStatus" => 1 }, CommandType::Suppressed, None)
+                         .map_err(status_error)?;
+
+        last_committed(&status)
+    }
+}
+
+/// A gate holding back documents until they are majority-committed.
+pub(crate) struct Majority<C> {
+    /// The provider of the majority-committed position.
+    position: C,
+    /// The last known majority-committed position, if any.
+    committed: Option<OpTime>,
+    /// The document read but held back until it is majority-committed, if any.
+    pending: Option<Document>,
+}
+
+impl<C: CommittedPosition> Majority<C> {
+    /// Returns a new gate reading the majority-committed position from the given provider.
+    pub(crate) fn new(position: C) -> Majority<C> {
+        Majority {
+            position: position,
+            committed: None,
+            pending: None,
+        }
+    }
+
+    /// Returns whether a document is being held back.
+    pub(crate) fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Discard any document being held back, e.g. when its source is resumed.
+    pub(crate) fn reset(&mut self) {
+        self.pending = None;
+    }
+
+    /// Returns the result of reading the next document from the given documents once it is
+    /// majority-committed.
+    ///
+    /// When tailing, this returns `None` rather than waiting indefinitely for a document to be
+    /// committed.
+    pub(crate) fn next<I>(&mut self, documents: &mut I, tailing: bool) -> Option<Result<Document>>
+        where I: Iterator<Item = Result<Document>>
+    {
+        let document = match self.pending.take() {
+            Some(document) => document,
+            None => {
+                match documents.next() {
+                    Some(Ok(document)) => document,
+                    Some(Err(err)) => return Some(Err(err)),
+                    None => return None,
+                }
+            }
+        };
+
+        loop {
+            match self.is_committed(&document) {
+                Ok(true) => return Some(Ok(document)),
+                Ok(false) if tailing => {
+                    self.pending = Some(document);
+
+                    return None;
+                }
+                Ok(false) => thread::sleep(POLL_INTERVAL),
+                Err(err) => {
+                    self.pending = Some(document);
+
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+
+    /// Returns whether the given document is majority-committed, polling the replica set if it is
+    /// after the last known majority-committed position.
+    ///
+    /// Documents without a valid position are returned as-is so the `Oplog` can report them.
+    fn is_committed(&mut self, document: &Document) -> Result<bool> {
+        let optime = match OpTime::from_document(document) {
+            Ok(optime) => optime,
+            Err(_) => return Ok(true),
+        };
+
+        if let Some(committed) = self.committed {
+            if committed >= optime.comparable_to(committed) {
+                return Ok(true);
+            }
+        }
+
+        let committed = self.position.committed()?;
+        self.committed = Some(committed);
+
+        Ok(committed >= optime.comparable_to(committed))
+    }
+}
+
+/// Returns the error for a failure to run `replSetGetStatus`, distinguishing the server refusing
+/// it (e.g. for lack of the `clusterMonitor` role) from failing to reach the server at all.
+fn status_error(err: mongodb::Error) -> Error {
+    match err {
+        mongodb::Error::OperationError(message) => Error::ReplicaSetStatus(message),
+        err => Error::Database(err),
+    }
+}
+
+/// Returns the majority-committed position from the result of a `replSetGetStatus` command.
+fn last_committed(status: &Document) -> Result<OpTime> {
+    if let Some(&Bson::String(ref message)) = status.get("errmsg") {
+        return Err(Error::ReplicaSetStatus(message.to_owned()));
+    }
+
+    let optimes = status.get_document("optimes")?;
+    let committed = optimes.get_document("lastCommittedOpTime")?;
+
+    OpTime::from_document(committed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::vec;
+
+    use bson::{Bson, Document};
+    use mongodb;
+    use {Error, OpTime, Oplog, OplogSource, Result};
+    use oplog::Step;
+    use super::{last_committed, status_error, CommittedPosition, Majority};
+
+    fn insert(seconds: i64) -> Document {
+        doc! {
+            "ts" => (Bson::TimeStamp(seconds << 32)),
+            "t" => 1i64,
+            "v" => 2,
+            "op" => "i",
+            "ns" => "foo.bar",
+            "o" => { "_id" => seconds }
+        }
+    }
+
+    /// A majority-committed position set by the test, counting how often it is polled.
+    #[derive(Clone)]
+    struct FakePosition {
+        seconds: Rc<Cell<u32>>,
+        polls: Rc<Cell<usize>>,
+        advance: bool,
+    }
+
+    impl FakePosition {
+        fn new(seconds: u32, advance: bool) -> FakePosition {
+            FakePosition {
+                seconds: Rc::new(Cell::new(seconds)),
+                polls: Rc::new(Cell::new(0)),
+                advance: advance,
+            }
+        }
+    }
+
+    impl CommittedPosition for FakePosition {
+        fn committed(&mut self) -> Result<OpTime> {
+            self.polls.set(self.polls.get() + 1);
+
+            if self.advance {
+                self.seconds.set(self.seconds.get() + 1);
+            }
+
+            Ok(OpTime::new(self.seconds.get(), 0, Some(1)))
+        }
+    }
+
+    /// A position that cannot be read as the user lacks the role to do so.
+    struct UnauthorizedPosition;
+
+    impl CommittedPosition for UnauthorizedPosition {
+        fn committed(&mut self) -> Result<OpTime> {
+            Err(Error::ReplicaSetStatus("not authorized on admin to execute command".into()))
+        }
+    }
+
+    /// A tailing source of the given documents held back until they are majority-committed.
+    struct MajoritySource<C> {
+        documents: vec::IntoIter<Result<Document>>,
+        majority: Majority<C>,
+    }
+
+    impl<C: CommittedPosition> OplogSource for MajoritySource<C> {
+        fn next_document(&mut self) -> Option<Result<Document>> {
+            self.majority.next(&mut self.documents, true)
+        }
+
+        fn is_tailing(&self) -> bool {
+            true
+        }
+    }
+
+    fn tailing<C: CommittedPosition>(documents: Vec<Document>,
+                                     position: C)
+                                     -> Oplog<MajoritySource<C>> {
+        Oplog::from_source(MajoritySource {
+            documents: documents.into_iter().map(Ok).collect::<Vec<_>>().into_iter(),
+            majority: Majority::new(position),
+        })
+    }
+
+    fn seconds(step: Step) -> Option<u32> {
+        match step {
+            Step::Entry(entry) => Some((*entry).as_ref().unwrap().0.optime().seconds),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn majority_holds_back_entries_until_committed_while_tailing() {
+        let position = FakePosition::new(1, false);
+        let mut oplog = tailing(vec![insert(1), insert(2), insert(3)], position.clone());
+
+        assert_eq!(seconds(oplog.step()), Some(1));
+        assert!(matches!(oplog.step(), Step::Pending));
+        assert!(matches!(oplog.step(), Step::Pending));
+
+        position.seconds.set(3);
+
+        assert_eq!(seconds(oplog.step()), Some(2));
+        assert_eq!(seconds(oplog.step()), Some(3));
+        assert!(matches!(oplog.step(), Step::Pending));
+    }
+
+    #[test]
+    fn majority_only_polls_for_entries_after_the_known_position() {
+        let position = FakePosition::new(3, false);
+        let mut oplog = tailing(vec![insert(1), insert(2), insert(3)], position.clone());
+
+        assert_eq!(seconds(oplog.step()), Some(1));
+        assert_eq!(seconds(oplog.step()), Some(2));
+        assert_eq!(seconds(oplog.step()), Some(3));
+        assert_eq!(position.polls.get(), 1);
+    }
+
+    #[test]
+    fn majority_waits_for_entries_to_be_committed_when_not_tailing() {
+        let position = FakePosition::new(0, true);
+        let mut majority = Majority::new(position.clone());
+        let mut documents = vec![Ok(insert(2))].into_iter();
+
+        assert_eq!(majority.next(&mut documents, false).unwrap().unwrap(), insert(2));
+        assert_eq!(position.polls.get(), 2);
+    }
+
+    #[test]
+    fn majority_discards_held_back_entries_when_reset() {
+        let mut majority = Majority::new(FakePosition::new(1, false));
+        let mut documents = vec![Ok(insert(2))].into_iter();
+
+        assert!(majority.next(&mut documents, true).is_none());
+        assert!(majority.is_pending());
+
+        majority.reset();
+
+        assert!(!majority.is_pending());
+    }
+
+    #[test]
+    fn majority_ends_the_oplog_when_the_replica_set_status_is_unavailable() {
+        let mut oplog = tailing(vec![insert(1)], UnauthorizedPosition);
+        let results: Vec<_> = oplog.try_iter().take(3).collect();
+
+        assert_eq!(results.len(), 1);
+        match results[0] {
+            Err(Error::ReplicaSetStatus(_)) => {}
+            _ => panic!("Expected replica set status error."),
+        }
+    }
+
+    #[test]
+    fn last_committed_reads_replica_set_status() {
+        let status = doc! {
+            "set" => "rs0",
+            "optimes" => {
+                "lastCommittedOpTime" => {
+                    "ts" => (Bson::TimeStamp(1479561394 << 32 | 3)),
+                    "t" => 2i64
+                },
+                "appliedOpTime" => {
+                    "ts" => (Bson::TimeStamp(1479561395 << 32 | 1)),
+                    "t" => 2i64
+                }
+            },
+            "ok" => 1.0
+        };
+
+        assert_eq!(last_committed(&status).unwrap(), OpTime::new(1479561394, 3, Some(2)));
+    }
+
+    #[test]
+    fn last_committed_returns_command_errors() {
+        let status = doc! { "ok" => 0.0, "errmsg" => "not running with --replSet", "code" => 76 };
+
+        match last_committed(&status) {
+            Err(Error::ReplicaSetStatus(_)) => {}
+            _ => panic!("Expected replica set status error."),
+        }
+    }
+
+    #[test]
+    fn status_error_distinguishes_refused_commands() {
+        let refused = mongodb::Error::OperationError("not authorized on admin".into());
+
+        match status_error(refused) {
+            Error::ReplicaSetStatus(_) => {}
+            _ => panic!("Expected replica set status error."),
+        }
+    }
+}
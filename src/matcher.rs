@@ -0,0 +1,293 @@
+//! The matcher module is responsible for evaluating a user's filter against an oplog document on
+//! the client.
+//!
+//! This is only used to tell no-ops read regardless of the filter (e.g. to advance a
+//! `MergedOplog`) apart from those the filter itself matched, so only the common query operators
+//! are supported and anything else is reported as unknown rather than guessed.
+
+use std::cmp::Ordering;
+
+use bson::{Bson, Document};
+use regex::Regex;
+
+/// Returns whether the given filter matches the given document or `None` if the filter uses an
+/// operator that cannot be evaluated.
+pub(crate) fn matches(filter: &Document, document: &Document) -> Option<bool> {
+    all(filter.iter().map(|(key, condition)| {
+        match key.as_str() {
+            "$and" => all(clauses(condition)?.iter().map(|clause| matches(clause, document))),
+            "$or" => any(clauses(condition)?.iter().map(|clause| matches(clause, document))),
+            "$nor" => {
+                any(clauses(condition)?.iter().map(|clause| matches(clause, document)))
+                    .map(|matched| !matched)
+            }
+            key if key.starts_with('$') => None,
+            path => matches_value(lookup(document, path), condition),
+        }
+    }))
+}
+
+/// Returns whether the given value, if any, satisfies the given condition on its field.
+fn matches_value(value: Option<&Bson>, condition: &Bson) -> Option<bool> {
+    match *condition {
+        Bson::Document(ref operators) if is_operators(operators) => {
+            all(operators.iter().map(|(operator, operand)| {
+                matches_operator(value, operator, operand, operators)
+            }))
+        }
+        Bson::RegExp(ref pattern, ref options) => matches_regex(value, pattern, options),
+        _ => Some(equals(value, condition)),
+    }
+}
+
+/// Returns whether the given value, if any, satisfies a single query operator.
+fn matches_operator(value: Option<&Bson>,
+                    operator: &str,
+                    operand: &Bson,
+                    operators: &Document)
+                    -> Option<bool> {
+    match operator {
+        "$eq" => Some(equals(value, operand)),
+        "$ne" => Some(!equals(value, operand)),
+        "$in" => {
+            match *operand {
+                // Regular expressions in `$in` match by pattern rather than equality.
+                Bson::Array(ref operands) if operands.iter().any(is_regex) => None,
+                Bson::Array(ref operands) => {
+                    Some(operands.iter().any(|operand| equals(value, operand)))
+                }
+                _ => None,
+            }
+        }
+        "$nin" => matches_operator(value, "$in", operand, operators).map(|matched| !matched),
+        "$exists" => {
+            match *operand {
+                Bson::Boolean(exists) => Some(value.is_some() == exists),
+                _ => None,
+            }
+        }
+        "$gt" => Some(compare(value, operand) == Some(Ordering::Greater)),
+        "$gte" => {
+            Some(matches!(compare(value, operand), Some(Ordering::Greater) | Some(Ordering::Equal)))
+        }
+        "$lt" => Some(compare(value, operand) == Some(Ordering::Less)),
+        "$lte" => {
+            Some(matches!(compare(value, operand), Some(Ordering::Less) | Some(Ordering::Equal)))
+        }
+        "$regex" => {
+            let options = operators.get_str("$options").unwrap_or("");
+
+            match *operand {
+                Bson::String(ref pattern) => matches_regex(value, pattern, options),
+                Bson::RegExp(ref pattern, _) => matches_regex(value, pattern, options),
+                _ => None,
+            }
+        }
+        "$options" if operators.contains_key("$regex") => Some(true),
+        "$not" => matches_value(value, operand).map(|matched| !matched),
+        _ => None,
+    }
+}
+
+/// Returns whether the given value, if any, is a string matching the given regular expression.
+fn matches_regex(value: Option<&Bson>, pattern: &str, options: &str) -> Option<bool> {
+    if options.chars().any(|option| !"imsx".contains(option)) {
+        return None;
+    }
+
+    let regex = if options.is_empty() {
+        Regex::new(pattern)
+    } else {
+        Regex::new(&format!("(?{}){}", options, pattern))
+    };
+
+    match (regex, value) {
+        (Ok(regex), Some(&Bson::String(ref value))) => Some(regex.is_match(value)),
+        (Ok(_), _) => Some(false),
+        (Err(_), _) => None,
+    }
+}
+
+/// Returns whether the given value, if any, equals the given operand, matching any element of an
+/// array and treating a missing value as null.
+fn equals(value: Option<&Bson>, operand: &Bson) -> bool {
+    match value {
+        None => *operand == Bson::Null,
+        Some(&Bson::Array(ref values)) if !is_array(operand) => {
+            values.iter().any(|value| equals(Some(value), operand))
+        }
+        Some(value) => compare(Some(value), operand) == Some(Ordering::Equal) || value == operand,
+    }
+}
+
+/// Returns the ordering of the given value, if any, relative to the given operand if they are
+/// comparable.
+fn compare(value: Option<&Bson>, operand: &Bson) -> Option<Ordering> {
+    match (value?, operand) {
+        (&Bson::String(ref value), &Bson::String(ref operand)) => Some(value.cmp(operand)),
+        (&Bson::TimeStamp(value), &Bson::TimeStamp(operand)) => {
+            Some((value as u64).cmp(&(operand as u64)))
+        }
+        (&Bson::UtcDatetime(ref value), &Bson::UtcDatetime(ref operand)) => {
+            Some(value.cmp(operand))
+        }
+        (value, operand) => number(value)?.partial_cmp(&number(operand)?),
+    }
+}
+
+/// Returns the given value as a number if it is one.
+fn number(value: &Bson) -> Option<f64> {
+    match *value {
+        Bson::I32(value) => Some(value as f64),
+        Bson::I64(value) => Some(value as f64),
+        Bson::FloatingPoint(value) => Some(value),
+        _ => None,
+    }
+}
+
+/// Returns the value at the given dotted path of the document, if any.
+fn lookup<'a>(document: &'a Document, path: &str) -> Option<&'a Bson> {
+    let mut fields = path.split('.');
+    let mut value = document.get(fields.next()?)?;
+
+    for field in fields {
+        value = match *value {
+            Bson::Document(ref document) => document.get(field)?,
+            _ => return None,
+        };
+    }
+
+    Some(value)
+}
+
+/// Returns the documents of a logical operator's array of clauses.
+fn clauses(condition: &Bson) -> Option<Vec<&Document>> {
+    match *condition {
+        Bson::Array(ref clauses) => {
+            clauses.iter()
+                   .map(|clause| match *clause {
+                       Bson::Document(ref clause) => Some(clause),
+                       _ => None,
+                   })
+                   .collect()
+        }
+        _ => None,
+    }
+}
+
+/// Returns whether the given document is a set of query operators rather than a value to match
+/// exactly.
+fn is_operators(document: &Document) -> bool {
+    match document.keys().next() {
+        Some(key) => key.starts_with('$'),
+        None => false,
+    }
+}
+
+/// Returns whether the given value is a regular expression.
+fn is_regex(value: &Bson) -> bool {
+    matches!(*value, Bson::RegExp(..))
+}
+
+/// Returns whether the given value is an array.
+fn is_array(value: &Bson) -> bool {
+    matches!(*value, Bson::Array(_))
+}
+
+/// Returns the conjunction of the given results, unknown if any is unknown and none is false.
+fn all<I: Iterator<Item = Option<bool>>>(results: I) -> Option<bool> {
+    let mut known = true;
+
+    for result in results {
+        match result {
+            Some(false) => return Some(false),
+            Some(true) => {}
+            None => known = false,
+        }
+    }
+
+    if known { Some(true) } else { None }
+}
+
+/// Returns the disjunction of the given results, unknown if any is unknown and none is true.
+fn any<I: Iterator<Item = Option<bool>>>(results: I) -> Option<bool> {
+    let mut known = true;
+
+    for result in results {
+        match result {
+            Some(true) => return Some(true),
+            Some(false) => {}
+            None => known = false,
+        }
+    }
+
+    if known { Some(false) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{Bson, Document};
+    use super::matches;
+
+    fn noop() -> Document {
+        doc! {
+            "ts" => (Bson::TimeStamp(1479561394 << 32 | 1)),
+            "v" => 2,
+            "op" => "n",
+            "ns" => "",
+            "o" => { "msg" => "periodic noop" }
+        }
+    }
+
+    #[test]
+    fn matches_equality() {
+        assert_eq!(matches(&doc! { "op" => "n" }, &noop()), Some(true));
+        assert_eq!(matches(&doc! { "op" => "i" }, &noop()), Some(false));
+        assert_eq!(matches(&doc! { "o.msg" => "periodic noop" }, &noop()), Some(true));
+        assert_eq!(matches(&doc! { "o.missing" => (Bson::Null) }, &noop()), Some(true));
+    }
+
+    #[test]
+    fn matches_comparison_operators() {
+        assert_eq!(matches(&doc! { "op" => { "$in" => ["i", "n"] } }, &noop()), Some(true));
+        assert_eq!(matches(&doc! { "op" => { "$nin" => ["i", "n"] } }, &noop()), Some(false));
+        assert_eq!(matches(&doc! { "v" => { "$gte" => 2, "$lt" => 3 } }, &noop()), Some(true));
+        assert_eq!(matches(&doc! { "o.msg" => { "$exists" => false } }, &noop()), Some(false));
+        assert_eq!(matches(&doc! { "op" => { "$not" => { "$eq" => "i" } } }, &noop()), Some(true));
+    }
+
+    #[test]
+    fn matches_regular_expressions() {
+        assert_eq!(matches(&doc! { "o.msg" => (Bson::RegExp("^PERIODIC".into(), "i".into())) },
+                           &noop()),
+                   Some(true));
+        assert_eq!(matches(&doc! { "ns" => { "$regex" => "^foo\\." } }, &noop()), Some(false));
+    }
+
+    #[test]
+    fn matches_nothing_for_regular_expressions_in_sets() {
+        let pattern = Bson::RegExp("^foo".into(), "".into());
+
+        assert_eq!(matches(&doc! { "ns" => { "$in" => [(pattern.clone())] } }, &noop()), None);
+        assert_eq!(matches(&doc! { "ns" => { "$nin" => ["", (pattern)] } }, &noop()), None);
+    }
+
+    #[test]
+    fn matches_logical_operators() {
+        assert_eq!(matches(&doc! { "$or" => [{ "op" => "i" }, { "op" => "n" }] }, &noop()),
+                   Some(true));
+        assert_eq!(matches(&doc! { "$and" => [{ "op" => "n" }, { "ns" => "foo.bar" }] }, &noop()),
+                   Some(false));
+        assert_eq!(matches(&doc! { "$nor" => [{ "op" => "i" }] }, &noop()), Some(true));
+    }
+
+    #[test]
+    fn matches_nothing_it_cannot_evaluate() {
+        assert_eq!(matches(&doc! { "o" => { "$size" => 1 } }, &noop()), None);
+        assert_eq!(matches(&doc! { "$where" => "true" }, &noop()), None);
+        assert_eq!(matches(&doc! { "$or" => [{ "op" => "i" }, { "$where" => "true" }] }, &noop()),
+                   None);
+        assert_eq!(matches(&doc! { "$or" => [{ "op" => "n" }, { "$where" => "true" }] }, &noop()),
+                   Some(true));
+    }
+}
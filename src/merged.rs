@@ -0,0 +1,286 @@
+//! The merged module is responsible for combining the oplogs of several replica sets, e.g. the
+//! shards of a sharded cluster, into a single iterator ordered by `OpTime`.
+//!
+//! As each oplog is only ordered within itself, an operation can only be yielded once every other
+//! oplog has advanced past it. Idle primaries write a no-op every ten seconds so every oplog is
+//! read with no-ops regardless of any filters to ensure it eventually advances.
+
+use bson::Document;
+
+use {CursorSource, Operation, Oplog, OplogBuilder, OplogSource, OpTime, Result, Transactions};
+use oplog::Step;
+
+/// A single oplog being merged.
+struct Shard<S> {
+    /// The oplog.
+    oplog: Oplog<S>,
+    /// The next operation read from the oplog but yet to be yielded, if any.
+    head: Option<(Operation, Document)>,
+    /// Whether the oplog has ended.
+    ended: bool,
+}
+
+/// MergedOplog represents the oplogs of several replica sets combined in order.
+///
+/// It implements the `Iterator` trait, yielding every operation of every oplog ordered by its
+/// timestamp. Operations are held back until every other oplog has read an entry at or after them
+/// or ended.
+///
+/// Any errors raised while reading an oplog will cause the iteration to end. Use `try_iter` to
+/// receive these errors instead.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate mongodb;
+/// # extern crate oplog;
+/// use mongodb::{Client, ThreadedClient};
+/// use oplog::{MergedOplog, OplogBuilder};
+///
+/// # fn main() {
+/// let shard1 = Client::connect("shard1", 27017).expect("Failed to connect to shard1.");
+/// let shard2 = Client::connect("shard2", 27017).expect("Failed to connect to shard2.");
+/// let builders = [OplogBuilder::new(&shard1), OplogBuilder::new(&shard2)];
+///
+/// if let Ok(oplog) = MergedOplog::new(&builders) {
+///     for operation in oplog {
+///         // Do something with operation in cluster-wide order...
+///     }
+/// }
+/// # }
+/// ```
+pub struct MergedOplog<S = CursorSource> {
+    shards: Vec<Shard<S>>,
+}
+
+impl<S: OplogSource> Iterator for MergedOplog<S> {
+    type Item = Operation;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_result().and_then(|result| result.ok())
+    }
+}
+
+/// An iterator over a `MergedOplog` yielding the result of reading each operation.
+///
+/// This is created by `MergedOplog::try_iter`.
+pub struct TryMergedOplog<'a, S: 'a = CursorSource> {
+    oplog: &'a mut MergedOplog<S>,
+}
+
+impl<'a, S: OplogSource> Iterator for TryMergedOplog<'a, S> {
+    type Item = Result<Operation>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.oplog.next_result()
+    }
+}
+
+impl MergedOplog {
+    /// Executes the query of every given builder and returns a `MergedOplog` of their oplogs.
+    ///
+    /// Each oplog is read with no-ops regardless of its filters so that an idle oplog does not
+    /// hold back the others indefinitely, but they are not yielded if excluded by its filters.
+    /// Filters using query operators that cannot be evaluated on the client (e.g. `$where` or
+    /// regular expressions within `$in`) may still yield such no-ops.
+    pub fn new(builders: &[OplogBuilder]) -> Result<MergedOplog> {
+        let oplogs = builders.iter()
+                             .map(|builder| builder.build_with_heartbeats())
+                             .collect::<Result<Vec<_>>>()?;
+
+        Ok(MergedOplog::from_oplogs(oplogs))
+    }
+}
+
+impl<S: OplogSource> MergedOplog<S> {
+    /// Returns a new `MergedOplog` of the given oplogs.
+    ///
+    /// Every oplog must include no-ops or otherwise regularly advance for operations to be
+    /// yielded while tailing.
+    pub fn from_oplogs(oplogs: Vec<Oplog<S>>) -> MergedOplog<S> {
+        MergedOplog {
+            shards: oplogs.into_iter()
+                          .map(|oplog| {
+                              Shard {
+                                  oplog: oplog,
+                                  head: None,
+                                  ended: false,
+                              }
+                          })
+                          .collect(),
+        }
+    }
+
+    /// Returns an iterator yielding each committed multi-document transaction as a single
+    /// `Operation::Transaction` rather than its individual oplog entries.
+    ///
+    /// See `Transactions` for more details.
+    pub fn transactions(mut self) -> Transactions<MergedOplog<S>> {
+        for shard in &mut self.shards {
+            shard.oplog.keep_transactions();
+        }
+
+        Transactions::new(self)
+    }
+
+    /// Returns an iterator yielding the result of reading each operation so that errors can be
+    /// handled rather than ending iteration.
+    pub fn try_iter(&mut self) -> TryMergedOplog<'_, S> {
+        TryMergedOplog { oplog: self }
+    }
+
+    /// Returns the result of reading the next operation, awaiting one if necessary.
+    fn next_result(&mut self) -> Option<Result<Operation>> {
+        loop {
+            match self.step() {
+                Step::Entry(entry) => return Some((*entry).map(|(operation, _)| operation)),
+                Step::Pending => continue,
+                Step::End => return None,
+            }
+        }
+    }
+
+    /// Make a single attempt to read from every oplog without a pending operation and yield the
+    /// earliest operation if every other oplog has advanced past it.
+    pub(crate) fn step(&mut self) -> Step {
+        for shard in &mut self.shards {
+            if shard.head.is_some() || shard.ended {
+                continue;
+            }
+
+            match shard.oplog.step() {
+                Step::Entry(entry) => {
+                    match *entry {
+                        Ok(entry) => shard.head = Some(entry),
+                        Err(err) => return Step::Entry(Box::new(Err(err))),
+                    }
+                }
+                Step::Pending => {}
+                Step::End => shard.ended = true,
+            }
+        }
+
+        let earliest = self.shards
+                           .iter()
+                           .enumerate()
+                           .filter_map(|(index, shard)| {
+                               shard.head
+                                    .as_ref()
+                                    .map(|&(ref operation, _)| (operation.optime(), index))
+                           })
+                           .min();
+
+        match earliest {
+            Some((optime, index)) if self.has_advanced_past(optime) => {
+                match self.shards[index].head.take() {
+                    Some(entry) => Step::Entry(Box::new(Ok(entry))),
+                    None => Step::Pending,
+                }
+            }
+            Some(_) => Step::Pending,
+            None if self.shards.iter().all(|shard| shard.ended) => Step::End,
+            None => Step::Pending,
+        }
+    }
+
+    /// Returns whether every oplog has ended or read an entry at or after the given position.
+    fn has_advanced_past(&self, optime: OpTime) -> bool {
+        self.shards.iter().all(|shard| {
+            shard.ended ||
+            match shard.oplog.last_optime() {
+                Some(last) => last.comparable_to(optime) >= optime.comparable_to(last),
+                None => false,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use bson::{Bson, Document};
+    use {Oplog, OplogSource, Result};
+    use oplog::Step;
+    use super::MergedOplog;
+
+    fn entry(op: &str, seconds: i64) -> Document {
+        doc! {
+            "ts" => (Bson::TimeStamp(seconds << 32)),
+            "v" => 2,
+            "op" => op,
+            "ns" => "foo.bar",
+            "o" => { "_id" => seconds }
+        }
+    }
+
+    fn noop(seconds: i64) -> Document {
+        doc! {
+            "ts" => (Bson::TimeStamp(seconds << 32)),
+            "v" => 2,
+            "op" => "n",
+            "ns" => "",
+            "o" => { "msg" => "periodic noop" }
+        }
+    }
+
+    /// A tailing source yielding the given documents and then awaiting more forever.
+    struct TailingSource {
+        documents: VecDeque<Document>,
+    }
+
+    impl OplogSource for TailingSource {
+        fn next_document(&mut self) -> Option<Result<Document>> {
+            self.documents.pop_front().map(Ok)
+        }
+
+        fn is_tailing(&self) -> bool {
+            true
+        }
+    }
+
+    fn tailing(documents: Vec<Document>) -> Oplog<TailingSource> {
+        Oplog::from_source(TailingSource { documents: documents.into_iter().collect() })
+    }
+
+    fn seconds<S: OplogSource>(oplog: &mut MergedOplog<S>, steps: usize) -> Vec<u32> {
+        let mut seconds = Vec::new();
+
+        for _ in 0..steps {
+            if let Step::Entry(entry) = oplog.step() {
+                if let Ok((operation, _)) = *entry {
+                    seconds.push(operation.optime().seconds);
+                }
+            }
+        }
+
+        seconds
+    }
+
+    #[test]
+    fn merged_oplog_orders_operations() {
+        let oplog = MergedOplog::from_oplogs(vec![
+            Oplog::from_source(vec![entry("i", 1), entry("i", 3), entry("i", 5)].into_iter()),
+            Oplog::from_source(vec![entry("i", 2), entry("i", 4)].into_iter()),
+        ]);
+        let seconds: Vec<_> = oplog.map(|operation| operation.optime().seconds).collect();
+
+        assert_eq!(seconds, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn merged_oplog_holds_back_operations_until_every_oplog_advances() {
+        let mut oplog = MergedOplog::from_oplogs(vec![tailing(vec![entry("i", 1), entry("i", 5)]),
+                                                      tailing(vec![entry("i", 2)])]);
+
+        assert_eq!(seconds(&mut oplog, 10), vec![1, 2]);
+    }
+
+    #[test]
+    fn merged_oplog_releases_operations_after_noops() {
+        let mut oplog = MergedOplog::from_oplogs(vec![tailing(vec![entry("i", 1), entry("i", 5)]),
+                                                      tailing(vec![entry("i", 2), noop(6)])]);
+
+        assert_eq!(seconds(&mut oplog, 10), vec![1, 2, 5]);
+    }
+}
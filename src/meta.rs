@@ -0,0 +1,243 @@
+//! The meta module is responsible for extracting the metadata common to every oplog entry
+//! regardless of its operation type, e.g. its position in the oplog and any session or transaction
+//! it belongs to.
+
+use bson::{Bson, Document};
+use bson::spec::BinarySubtype;
+use chrono::{DateTime, UTC, TimeZone};
+use {OpTime, Result};
+
+/// The metadata shared by every MongoDB oplog operation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OperationMeta {
+    /// A unique identifier for this operation, if written by the server (before MongoDB 4.2).
+    pub id: Option<i64>,
+    /// The position of the operation in the oplog, including its term.
+    pub optime: OpTime,
+    /// The version of the oplog entry format.
+    pub version: i32,
+    /// The wall clock time at which the operation was written, if recorded.
+    pub wall_time: Option<DateTime<UTC>>,
+    /// The UUID of the collection affected by the operation, if recorded.
+    pub collection_uuid: Option<Vec<u8>>,
+    /// The logical session identifier of a retryable write or transaction.
+    pub session_id: Option<Document>,
+    /// The transaction number within the session of a retryable write or transaction.
+    pub txn_number: Option<i64>,
+    /// The statement identifiers of a retryable write.
+    pub statement_ids: Vec<i32>,
+    /// The position of the previous operation written in the same session.
+    pub prev_optime: Option<OpTime>,
+    /// Whether the operation was written by a chunk migration rather than a client.
+    pub from_migrate: bool,
+}
+
+impl OperationMeta {
+    /// Try to extract the metadata of an operation from a BSON oplog entry.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use]
+    /// # extern crate bson;
+    /// # extern crate oplog;
+    /// # use bson::Bson;
+    /// use oplog::{OperationMeta, OpTime};
+    ///
+    /// # fn main() {
+    /// let document = doc! {
+    ///     "ts" => (Bson::TimeStamp(1479561394 << 32)),
+    ///     "t" => 1i64,
+    ///     "v" => 2,
+    ///     "op" => "i",
+    ///     "ns" => "foo.bar",
+    ///     "o" => {
+    ///         "foo" => "bar"
+    ///     }
+    /// };
+    /// let meta = OperationMeta::new(&document).unwrap();
+    ///
+    /// assert_eq!(meta.optime, OpTime::new(1479561394, 0, Some(1)));
+    /// # }
+    /// ```
+    pub fn new(document: &Document) -> Result<OperationMeta> {
+        let prev_optime = match document.get("prevOpTime") {
+            Some(&Bson::Document(ref prev)) => null_optime(OpTime::from_document(prev)?),
+            _ => None,
+        };
+        let wall_time = document.get_utc_datetime("wall").ok().map(|wall| {
+            UTC.timestamp(wall.timestamp(), wall.timestamp_subsec_nanos())
+        });
+
+        Ok(OperationMeta {
+            id: id(document)?,
+            optime: OpTime::from_document(document)?,
+            version: version(document),
+            wall_time: wall_time,
+            collection_uuid: match document.get("ui") {
+                Some(&Bson::Binary(BinarySubtype::Uuid, ref uuid)) => Some(uuid.to_owned()),
+                _ => None,
+            },
+            session_id: document.get_document("lsid").ok().map(|lsid| lsid.to_owned()),
+            txn_number: integer(document.get("txnNumber")),
+            statement_ids: match document.get("stmtId") {
+                Some(&Bson::Array(ref ids)) => {
+                    ids.iter().filter_map(|id| integer(Some(id))).map(|id| id as i32).collect()
+                }
+                id => integer(id).map(|id| id as i32).into_iter().collect(),
+            },
+            prev_optime: prev_optime,
+            from_migrate: document.get_bool("fromMigrate").unwrap_or(false),
+        })
+    }
+}
+
+/// Returns the unique identifier of an oplog entry from its `h` field.
+///
+/// Only version 1 entries are guaranteed to have one: from MongoDB 4.2 the server either omits it
+/// or writes zero, in which case the entry's `OpTime` is its only stable identity.
+fn id(document: &Document) -> Result<Option<i64>> {
+    if version(document) < 2 {
+        return Ok(Some(document.get_i64("h")?));
+    }
+
+    match document.get("h") {
+        Some(&Bson::I64(0)) | None => Ok(None),
+        Some(_) => Ok(Some(document.get_i64("h")?)),
+    }
+}
+
+/// Returns the oplog version of an entry from its `v` field.
+///
+/// Entries nested inside `applyOps` commands do not carry a version so we assume the current one.
+fn version(document: &Document) -> i32 {
+    integer(document.get("v")).map(|v| v as i32).unwrap_or(2)
+}
+
+/// Returns the value of a BSON integer regardless of its width.
+fn integer(bson: Option<&Bson>) -> Option<i64> {
+    match bson {
+        Some(&Bson::I32(i)) => Some(i as i64),
+        Some(&Bson::I64(i)) => Some(i),
+        _ => None,
+    }
+}
+
+/// Returns `None` for the null `OpTime` the server uses to mark the start of a chain.
+fn null_optime(optime: OpTime) -> Option<OpTime> {
+    if optime.timestamp() == 0 {
+        None
+    } else {
+        Some(optime)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use OpTime;
+    use bson::Bson;
+    use bson::spec::BinarySubtype;
+    use chrono::{UTC, TimeZone};
+    use super::OperationMeta;
+
+    #[test]
+    fn meta_converts_modern_entries() {
+        let doc = doc! {
+            "lsid" => {
+                "id" => (Bson::Binary(BinarySubtype::Uuid, vec![1, 2, 3]))
+            },
+            "txnNumber" => 4i64,
+            "op" => "i",
+            "ns" => "foo.bar",
+            "ui" => (Bson::Binary(BinarySubtype::Uuid, vec![4, 5, 6])),
+            "o" => {
+                "_id" => 1
+            },
+            "ts" => (Bson::TimeStamp(1479561394 << 32 | 2)),
+            "t" => 3i64,
+            "v" => 2i64,
+            "wall" => (Bson::from_extended_document(doc! {
+                "$date" => { "$numberLong" => 1479561394123i64 }
+            })),
+            "stmtId" => 0,
+            "prevOpTime" => {
+                "ts" => (Bson::TimeStamp(1479561394 << 32 | 1)),
+                "t" => 3i64
+            },
+            "fromMigrate" => true
+        };
+        let meta = OperationMeta::new(&doc).unwrap();
+
+        assert_eq!(meta,
+                   OperationMeta {
+                       id: None,
+                       optime: OpTime::new(1479561394, 2, Some(3)),
+                       version: 2,
+                       wall_time: Some(UTC.timestamp(1479561394, 123000000)),
+                       collection_uuid: Some(vec![4, 5, 6]),
+                       session_id: Some(doc! {
+                           "id" => (Bson::Binary(BinarySubtype::Uuid, vec![1, 2, 3]))
+                       }),
+                       txn_number: Some(4),
+                       statement_ids: vec![0],
+                       prev_optime: Some(OpTime::new(1479561394, 1, Some(3))),
+                       from_migrate: true,
+                   });
+    }
+
+    #[test]
+    fn meta_converts_legacy_entries() {
+        let doc = doc! {
+            "ts" => (Bson::TimeStamp(1479561394 << 32)),
+            "h" => (-1742072865587022793i64),
+            "v" => 2,
+            "op" => "i",
+            "ns" => "foo.bar",
+            "o" => {
+                "foo" => "bar"
+            }
+        };
+        let meta = OperationMeta::new(&doc).unwrap();
+
+        assert_eq!(meta,
+                   OperationMeta {
+                       id: Some(-1742072865587022793i64),
+                       optime: OpTime::new(1479561394, 0, None),
+                       version: 2,
+                       wall_time: None,
+                       collection_uuid: None,
+                       session_id: None,
+                       txn_number: None,
+                       statement_ids: vec![],
+                       prev_optime: None,
+                       from_migrate: false,
+                   });
+    }
+
+    #[test]
+    fn meta_ignores_null_previous_optimes() {
+        let doc = doc! {
+            "ts" => (Bson::TimeStamp(1479561394 << 32)),
+            "op" => "c",
+            "prevOpTime" => {
+                "ts" => (Bson::TimeStamp(0)),
+                "t" => (-1i64)
+            }
+        };
+        let meta = OperationMeta::new(&doc).unwrap();
+
+        assert_eq!(meta.prev_optime, None);
+    }
+
+    #[test]
+    fn meta_converts_multiple_statement_ids() {
+        let doc = doc! {
+            "ts" => (Bson::TimeStamp(1479561394 << 32)),
+            "op" => "i",
+            "stmtId" => [0, 1, 2]
+        };
+        let meta = OperationMeta::new(&doc).unwrap();
+
+        assert_eq!(meta.statement_ids, vec![0, 1, 2]);
+    }
+}
@@ -0,0 +1,363 @@
+//! The namespace module is responsible for restricting the operations read from the oplog to those
+//! in namespaces given by the user, both on the server when querying the oplog and on the client
+//! for the operations nested within commands.
+//!
+//! Namespaces can be given exactly (e.g. `app.users`), as every collection in a database (e.g.
+//! `billing.*`) or as a regular expression between slashes (e.g. `/^app\.logs_/`).
+
+use bson::{Bson, Document};
+use regex::{self, Regex};
+
+use {CommandKind, Operation, Result};
+
+/// A pattern matching the full namespace of an operation.
+#[derive(Clone, Debug)]
+enum Pattern {
+    /// A single namespace.
+    Exact(String),
+    /// Every namespace in a database.
+    Database(String),
+    /// Any namespace matching a regular expression anywhere within it.
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// Try to parse a pattern, returning an error if it is an invalid regular expression.
+    fn new(pattern: &str) -> Result<Pattern> {
+        if pattern.len() > 1 && pattern.starts_with('/') && pattern.ends_with('/') {
+            Ok(Pattern::Regex(Regex::new(&pattern[1..pattern.len() - 1])?))
+        } else if let Some(database) = pattern.strip_suffix(".*") {
+            Ok(Pattern::Database(database.to_owned()))
+        } else {
+            Ok(Pattern::Exact(pattern.to_owned()))
+        }
+    }
+
+    /// Returns whether the pattern matches the given namespace.
+    fn matches(&self, namespace: &str) -> bool {
+        match *self {
+            Pattern::Exact(ref exact) => namespace == exact,
+            Pattern::Database(ref database) => database_of(namespace) == database,
+            Pattern::Regex(ref regex) => regex.is_match(namespace),
+        }
+    }
+
+    /// Returns whether the pattern matches any namespace in the given database.
+    ///
+    /// Regular expressions are matched against the database's command namespace.
+    fn matches_any_in(&self, database: &str) -> bool {
+        match *self {
+            Pattern::Exact(ref exact) => database_of(exact) == database,
+            Pattern::Database(ref other) => other == database,
+            Pattern::Regex(ref regex) => regex.is_match(&format!("{}.$cmd", database)),
+        }
+    }
+
+    /// Returns whether the pattern matches every namespace in the given database.
+    fn matches_all_in(&self, database: &str) -> bool {
+        match *self {
+            Pattern::Database(ref other) => other == database,
+            _ => false,
+        }
+    }
+
+    /// Returns the pattern as a BSON value for use with `$in` and `$nin`.
+    fn to_bson(&self) -> Bson {
+        match *self {
+            Pattern::Exact(ref exact) => Bson::String(exact.to_owned()),
+            Pattern::Database(ref database) => {
+                Bson::RegExp(format!("^{}\\.", regex::escape(database)), String::new())
+            }
+            Pattern::Regex(ref regex) => Bson::RegExp(regex.as_str().to_owned(), String::new()),
+        }
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Pattern) -> bool {
+        match (self, other) {
+            (&Pattern::Exact(ref a), &Pattern::Exact(ref b)) |
+            (&Pattern::Database(ref a), &Pattern::Database(ref b)) => a == b,
+            (&Pattern::Regex(ref a), &Pattern::Regex(ref b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
+
+/// The namespaces to include and exclude when reading the oplog.
+///
+/// An operation is included if it matches any included pattern (or there are none) and no
+/// excluded pattern.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Namespaces {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl Namespaces {
+    /// Try to create new `Namespaces` from the given patterns to include and exclude.
+    ///
+    /// Returns an `Error::InvalidNamespace` if any regular expression is invalid.
+    pub fn new<S: AsRef<str>>(include: &[S], exclude: &[S]) -> Result<Namespaces> {
+        Ok(Namespaces {
+            include: patterns(include)?,
+            exclude: patterns(exclude)?,
+        })
+    }
+
+    /// Returns the clauses to add to the server-side query for these namespaces.
+    ///
+    /// Commands are always read as their namespace (e.g. `app.$cmd`) does not reflect the
+    /// namespaces they affect, e.g. the operations within an `applyOps`.
+    pub fn clauses(&self) -> Vec<Document> {
+        let mut clauses = Vec::new();
+
+        if !self.include.is_empty() {
+            clauses.push(doc! {
+                "$or" => [
+                    { "ns" => { "$in" => (to_bson(&self.include)) } },
+                    { "op" => "c" }
+                ]
+            });
+        }
+
+        if !self.exclude.is_empty() {
+            clauses.push(doc! {
+                "$or" => [
+                    { "ns" => { "$nin" => (to_bson(&self.exclude)) } },
+                    { "op" => "c" }
+                ]
+            });
+        }
+
+        clauses
+    }
+
+    /// Returns the given operation without any nested operations outside these namespaces or
+    /// `None` if it lies outside them entirely.
+    ///
+    /// Commands are matched by the namespace they affect, e.g. the collection being created. The
+    /// entries needed to reassemble transactions are kept regardless when keeping transactions.
+    pub fn retain(&self, operation: Operation, transactions: bool) -> Option<Operation> {
+        if self.include.is_empty() && self.exclude.is_empty() {
+            return Some(operation);
+        }
+
+        operation.retain(&|operation| self.includes(operation), transactions)
+    }
+
+    /// Returns whether the given operation, ignoring any nested operations, is included.
+    fn includes(&self, operation: &Operation) -> bool {
+        match *operation {
+            Operation::Noop { .. } => self.matches(""),
+            Operation::Insert { ref namespace, .. } |
+            Operation::Update { ref namespace, .. } |
+            Operation::Delete { ref namespace, .. } => self.matches(namespace),
+            Operation::Command { ref namespace, ref command, .. } => {
+                self.matches_command(namespace, command)
+            }
+            Operation::ApplyOps { .. } | Operation::Transaction { .. } => false,
+            Operation::Unknown { ref raw, .. } => {
+                match raw.get_str("ns") {
+                    Ok(namespace) => self.matches(namespace),
+                    Err(_) => true,
+                }
+            }
+        }
+    }
+
+    /// Returns whether the given namespace is included.
+    fn matches(&self, namespace: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(namespace))) &&
+        !self.exclude.iter().any(|p| p.matches(namespace))
+    }
+
+    /// Returns whether any namespace in the given database is included.
+    fn matches_database(&self, database: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches_any_in(database))) &&
+        !self.exclude.iter().any(|p| p.matches_all_in(database))
+    }
+
+    /// Returns whether the namespace affected by a command with the given namespace is included.
+    fn matches_command(&self, namespace: &str, command: &CommandKind) -> bool {
+        let database = database_of(namespace);
+
+        match *command {
+            CommandKind::Create { ref collection, .. } |
+            CommandKind::Drop { ref collection } |
+            CommandKind::CreateIndexes { ref collection, .. } |
+            CommandKind::DropIndexes { ref collection, .. } |
+            CommandKind::CollMod { ref collection, .. } |
+            CommandKind::ConvertToCapped { ref collection, .. } |
+            CommandKind::EmptyCapped { ref collection } => {
+                self.matches(&format!("{}.{}", database, collection))
+            }
+            CommandKind::RenameCollection { ref from, ref to, .. } => {
+                self.matches(from) || self.matches(to)
+            }
+            CommandKind::DropDatabase => self.matches_database(database),
+            CommandKind::CommitTransaction { .. } |
+            CommandKind::AbortTransaction |
+            CommandKind::Other(_) => self.matches(namespace),
+        }
+    }
+}
+
+/// Try to parse every given pattern.
+fn patterns<S: AsRef<str>>(patterns: &[S]) -> Result<Vec<Pattern>> {
+    patterns.iter().map(|pattern| Pattern::new(pattern.as_ref())).collect()
+}
+
+/// Returns the given patterns as a BSON array.
+fn to_bson(patterns: &[Pattern]) -> Bson {
+    Bson::Array(patterns.iter().map(Pattern::to_bson).collect())
+}
+
+/// Returns the database of the given namespace.
+fn database_of(namespace: &str) -> &str {
+    namespace.split('.').next().unwrap_or(namespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{Bson, Document};
+    use {Error, Operation};
+    use super::Namespaces;
+
+    fn operation(op: &str, namespace: &str, o: Document) -> Operation {
+        Operation::new(&doc! {
+            "ts" => (Bson::TimeStamp(1479561394 << 32)),
+            "v" => 2,
+            "op" => op,
+            "ns" => namespace,
+            "o" => o
+        })
+            .unwrap()
+    }
+
+    fn namespaces(include: &[&str], exclude: &[&str]) -> Namespaces {
+        Namespaces::new(include, exclude).unwrap()
+    }
+
+    #[test]
+    fn namespaces_match_exact_names() {
+        let namespaces = namespaces(&["app.users"], &[]);
+
+        assert!(namespaces.matches("app.users"));
+        assert!(!namespaces.matches("app.users_old"));
+    }
+
+    #[test]
+    fn namespaces_match_databases() {
+        let namespaces = namespaces(&["billing.*"], &[]);
+
+        assert!(namespaces.matches("billing.invoices"));
+        assert!(!namespaces.matches("billing_old.invoices"));
+    }
+
+    #[test]
+    fn namespaces_match_regexes() {
+        let namespaces = namespaces(&[r"/^app\.logs_\d+$/"], &[]);
+
+        assert!(namespaces.matches("app.logs_2017"));
+        assert!(!namespaces.matches("app.logs"));
+    }
+
+    #[test]
+    fn namespaces_match_everything_but_exclusions() {
+        let namespaces = namespaces(&["app.*"], &["app.sessions"]);
+
+        assert!(namespaces.matches("app.users"));
+        assert!(!namespaces.matches("app.sessions"));
+    }
+
+    #[test]
+    fn namespaces_reject_invalid_regexes() {
+        match Namespaces::new(&["/(/"], &[]) {
+            Err(Error::InvalidNamespace(_)) => {}
+            _ => panic!("Expected invalid namespace."),
+        }
+    }
+
+    #[test]
+    fn namespaces_compose_server_side_clauses() {
+        let namespaces = namespaces(&["app.users", "billing.*", "/^logs/"], &["billing.tmp"]);
+
+        assert_eq!(namespaces.clauses(),
+                   vec![doc! {
+                            "$or" => [
+                                {
+                                    "ns" => {
+                                        "$in" => [
+                                            "app.users",
+                                            (Bson::RegExp(r"^billing\.".into(), "".into())),
+                                            (Bson::RegExp("^logs".into(), "".into()))
+                                        ]
+                                    }
+                                },
+                                { "op" => "c" }
+                            ]
+                        },
+                        doc! {
+                            "$or" => [
+                                { "ns" => { "$nin" => ["billing.tmp"] } },
+                                { "op" => "c" }
+                            ]
+                        }]);
+    }
+
+    #[test]
+    fn namespaces_retain_nested_operations() {
+        let namespaces = namespaces(&["app.users"], &[]);
+        let apply_ops = operation("c",
+                                  "admin.$cmd",
+                                  doc! {
+                                      "applyOps" => [
+                                          { "op" => "i", "ns" => "app.users", "o" => {} },
+                                          { "op" => "i", "ns" => "app.posts", "o" => {} }
+                                      ]
+                                  });
+
+        match namespaces.retain(apply_ops, false) {
+            Some(Operation::ApplyOps { operations, .. }) => {
+                assert_eq!(operations.len(), 1);
+                assert_eq!(operations[0], operation("i", "app.users", doc! {}));
+            }
+            _ => panic!("Expected apply ops."),
+        }
+    }
+
+    #[test]
+    fn namespaces_drop_emptied_apply_ops() {
+        let namespaces = namespaces(&["app.users"], &[]);
+        let apply_ops = operation("c",
+                                  "admin.$cmd",
+                                  doc! {
+                                      "applyOps" => [
+                                          { "op" => "i", "ns" => "app.posts", "o" => {} }
+                                      ]
+                                  });
+
+        assert_eq!(namespaces.retain(apply_ops, false), None);
+    }
+
+    #[test]
+    fn namespaces_match_commands_by_affected_namespace() {
+        let namespaces = namespaces(&["app.users"], &[]);
+
+        assert!(namespaces.retain(operation("c", "app.$cmd", doc! { "drop" => "users" }), false)
+                          .is_some());
+        assert!(namespaces.retain(operation("c", "app.$cmd", doc! { "drop" => "posts" }), false)
+                          .is_none());
+        assert!(namespaces.retain(operation("c", "app.$cmd", doc! { "dropDatabase" => 1 }), false)
+                          .is_some());
+    }
+
+    #[test]
+    fn namespaces_only_exclude_drop_databases_of_excluded_databases() {
+        let namespaces = namespaces(&[], &["app.sessions"]);
+
+        assert!(namespaces.retain(operation("c", "app.$cmd", doc! { "dropDatabase" => 1 }), false)
+                          .is_some());
+    }
+}
@@ -1,7 +1,7 @@
 //! The operation module is responsible for converting MongoDB BSON documents into specific
 //! `Operation` types, one for each type of document stored in the MongoDB oplog. As much as
-//! possible, we convert BSON types into more typical Rust types (e.g. BSON timestamps into UTC
-//! datetimes).
+//! possible, we convert BSON types into more typical Rust types (e.g. BSON timestamps into
+//! `OpTime`s).
 //!
 //! As we accept _any_ document, it may not be a valid operation so wrap any conversions in a
 //! `Result`.
@@ -9,27 +9,23 @@
 use std::fmt;
 
 use bson::{Bson, Document};
-use chrono::{DateTime, UTC, TimeZone};
-use {Error, Result};
+use chrono::{DateTime, UTC};
+use {CommandKind, Error, OperationKind, OperationMeta, OpTime, Result};
 
 /// A MongoDB oplog operation.
 #[derive(Clone, Debug, PartialEq)]
 pub enum Operation {
     /// A no-op as inserted periodically by MongoDB or used to initiate new replica sets.
     Noop {
-        /// A unique identifier for this operation.
-        id: i64,
-        /// The time of the operation.
-        timestamp: DateTime<UTC>,
+        /// The metadata of the operation such as its position in the oplog.
+        meta: OperationMeta,
         /// The message associated with this operation.
         message: String,
     },
     /// An insert of a document into a specific database and collection.
     Insert {
-        /// A unique identifier for this operation.
-        id: i64,
-        /// The time of the operation.
-        timestamp: DateTime<UTC>,
+        /// The metadata of the operation such as its position in the oplog.
+        meta: OperationMeta,
         /// The full namespace of the operation including its database and collection.
         namespace: String,
         /// The BSON document inserted into the namespace.
@@ -37,23 +33,21 @@ pub enum Operation {
     },
     /// An update of a document in a specific database and collection matching a given query.
     Update {
-        /// A unique identifier for this operation.
-        id: i64,
-        /// The time of the operation.
-        timestamp: DateTime<UTC>,
+        /// The metadata of the operation such as its position in the oplog.
+        meta: OperationMeta,
         /// The full namespace of the operation including its database and collection.
         namespace: String,
         /// The BSON selection criteria for the update.
         query: Document,
         /// The BSON update applied in this operation.
+        ///
+        /// Use `UpdateDescription` to interpret this regardless of its format.
         update: Document,
     },
     /// The deletion of a document in a specific database and collection matching a given query.
     Delete {
-        /// A unique identifier for this operation.
-        id: i64,
-        /// The time of the operation.
-        timestamp: DateTime<UTC>,
+        /// The metadata of the operation such as its position in the oplog.
+        meta: OperationMeta,
         /// The full namespace of the operation including its database and collection.
         namespace: String,
         /// The BSON selection criteria for the delete.
@@ -61,25 +55,44 @@ pub enum Operation {
     },
     /// A command such as the creation or deletion of a collection.
     Command {
-        /// A unique identifier for this operation.
-        id: i64,
-        /// The time of the operation.
-        timestamp: DateTime<UTC>,
+        /// The metadata of the operation such as its position in the oplog.
+        meta: OperationMeta,
         /// The full namespace of the operation including its database and collection.
         namespace: String,
-        /// The BSON command.
-        command: Document,
+        /// The command, e.g. the creation of a collection.
+        command: CommandKind,
     },
     /// A command to apply multiple oplog operations at once.
     ApplyOps {
-        /// A unique identifier for this operation.
-        id: i64,
-        /// The time of the operation.
-        timestamp: DateTime<UTC>,
+        /// The metadata of the operation such as its position in the oplog.
+        meta: OperationMeta,
         /// The full namespace of the operation including its database and collection.
         namespace: String,
         /// A vector of operations to apply.
         operations: Vec<Operation>,
+        /// Whether this is one of several entries making up a single large transaction.
+        partial: bool,
+        /// Whether this entry prepares a transaction to be committed or aborted by a later command.
+        prepare: bool,
+    },
+    /// A multi-document transaction reassembled from one or more `applyOps` entries by
+    /// `Transactions`.
+    ///
+    /// This is never returned by `Operation::new` as a transaction can span several oplog entries.
+    Transaction {
+        /// The metadata of the entry that committed the transaction.
+        meta: OperationMeta,
+        /// A vector of every operation in the transaction.
+        operations: Vec<Operation>,
+    },
+    /// An entry of an unsupported operation type or that could not otherwise be converted.
+    ///
+    /// This is only returned by `Operation::new_lenient`.
+    Unknown {
+        /// The metadata of the operation such as its position in the oplog.
+        meta: OperationMeta,
+        /// The original BSON document of the entry.
+        raw: Document,
     },
 }
 
@@ -110,50 +123,195 @@ impl Operation {
     /// # }
     /// ```
     pub fn new(document: &Document) -> Result<Operation> {
-        let op = document.get_str("op")?;
-
-        match op {
-            "n" => Operation::from_noop(document),
-            "i" => Operation::from_insert(document),
-            "u" => Operation::from_update(document),
-            "d" => Operation::from_delete(document),
-            "c" => Operation::from_command(document),
-            op => Err(Error::UnknownOperation(op.into())),
+        Operation::parse(document, false)
+    }
+
+    /// Try to create a new Operation from a BSON document, preserving any document that cannot be
+    /// converted as an `Operation::Unknown` rather than returning an error.
+    ///
+    /// This still returns an error if the document's position in the oplog cannot be read.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use]
+    /// # extern crate bson;
+    /// # extern crate oplog;
+    /// # use bson::Bson;
+    /// use oplog::Operation;
+    ///
+    /// # fn main() {
+    /// let document = doc! {
+    ///     "ts" => (Bson::TimeStamp(1479561394 << 32)),
+    ///     "op" => "db",
+    ///     "ns" => "foo"
+    /// };
+    /// let operation = Operation::new_lenient(&document);
+    /// # }
+    /// ```
+    pub fn new_lenient(document: &Document) -> Result<Operation> {
+        Operation::parse(document, true)
+    }
+
+    /// Returns an operation for a given document, leniently or otherwise.
+    fn parse(document: &Document, lenient: bool) -> Result<Operation> {
+        let operation = document.get_str("op").map_err(Error::from).and_then(|op| {
+            match op {
+                "n" => Operation::from_noop(document),
+                "i" => Operation::from_insert(document),
+                "u" => Operation::from_update(document),
+                "d" => Operation::from_delete(document),
+                "c" => Operation::from_command(document, lenient),
+                op => Err(Error::UnknownOperation(op.into())),
+            }
+        });
+
+        match operation {
+            Err(_) if lenient => {
+                Ok(Operation::Unknown {
+                    meta: OperationMeta::new(document)?,
+                    raw: document.to_owned(),
+                })
+            }
+            operation => operation,
+        }
+    }
+
+    /// Returns the metadata shared by every type of operation.
+    pub fn meta(&self) -> &OperationMeta {
+        match *self {
+            Operation::Noop { ref meta, .. } |
+            Operation::Insert { ref meta, .. } |
+            Operation::Update { ref meta, .. } |
+            Operation::Delete { ref meta, .. } |
+            Operation::Command { ref meta, .. } |
+            Operation::ApplyOps { ref meta, .. } |
+            Operation::Transaction { ref meta, .. } |
+            Operation::Unknown { ref meta, .. } => meta,
+        }
+    }
+
+    /// Returns the position of this operation in the oplog.
+    pub fn optime(&self) -> OpTime {
+        self.meta().optime
+    }
+
+    /// Returns the time of this operation as a UTC `DateTime`.
+    ///
+    /// This is only precise to the second so use `optime` to order or resume operations.
+    pub fn timestamp(&self) -> DateTime<UTC> {
+        self.optime().datetime()
+    }
+
+    /// Returns the kind of this operation.
+    pub fn kind(&self) -> OperationKind {
+        match *self {
+            Operation::Noop { .. } => OperationKind::Noop,
+            Operation::Insert { .. } => OperationKind::Insert,
+            Operation::Update { .. } => OperationKind::Update,
+            Operation::Delete { .. } => OperationKind::Delete,
+            Operation::Command { .. } => OperationKind::Command,
+            Operation::ApplyOps { .. } => OperationKind::ApplyOps,
+            Operation::Transaction { .. } => OperationKind::Transaction,
+            Operation::Unknown { .. } => OperationKind::Unknown,
+        }
+    }
+
+    /// Returns this operation if the given predicate keeps it or, if it is an `ApplyOps` or
+    /// `Transaction` that is not kept, a copy with only the nested operations that are.
+    ///
+    /// Returns `None` if nothing is kept. When keeping transactions, the entries of multi-document
+    /// transactions and the commands committing or aborting them are kept even if empty or not
+    /// otherwise kept so that `Transactions` can still reassemble them.
+    pub(crate) fn retain<F>(self, keep: &F, transactions: bool) -> Option<Operation>
+        where F: Fn(&Operation) -> bool
+    {
+        if keep(&self) || (transactions && self.ends_transaction()) {
+            return Some(self);
+        }
+
+        match self {
+            Operation::ApplyOps { meta, namespace, operations, partial, prepare } => {
+                let operations = retain_all(operations, keep, transactions);
+
+                if operations.is_empty() && !(transactions && meta.txn_number.is_some()) {
+                    return None;
+                }
+
+                Some(Operation::ApplyOps {
+                    meta: meta,
+                    namespace: namespace,
+                    operations: operations,
+                    partial: partial,
+                    prepare: prepare,
+                })
+            }
+            Operation::Transaction { meta, operations } => {
+                let operations = retain_all(operations, keep, transactions);
+
+                if operations.is_empty() {
+                    return None;
+                }
+
+                Some(Operation::Transaction {
+                    meta: meta,
+                    operations: operations,
+                })
+            }
+            _ => None,
         }
     }
 
-    /// Returns an operation from any BSON value.
-    fn from_bson(bson: &Bson) -> Result<Operation> {
+    /// Returns whether this operation commits or aborts a multi-document transaction.
+    fn ends_transaction(&self) -> bool {
+        matches!(*self,
+                 Operation::Command { command: CommandKind::CommitTransaction { .. }, .. } |
+                 Operation::Command { command: CommandKind::AbortTransaction, .. })
+    }
+
+    /// Returns an operation nested in an `applyOps` command from any BSON value.
+    ///
+    /// Servers do not write a position for nested operations so they inherit the position of the
+    /// command containing them.
+    fn from_bson(bson: &Bson, meta: &OperationMeta, lenient: bool) -> Result<Operation> {
         match *bson {
-            Bson::Document(ref document) => Operation::new(document),
+            Bson::Document(ref document) if document.contains_key("ts") => {
+                Operation::parse(document, lenient)
+            }
+            Bson::Document(ref document) => {
+                let mut document = document.to_owned();
+                document.insert("ts", meta.optime);
+
+                if let Some(term) = meta.optime.term {
+                    document.insert("t", term);
+                }
+
+                Operation::parse(&document, lenient)
+            }
             _ => Err(Error::InvalidOperation),
         }
     }
 
     /// Returns a no-op operation for a given document.
     fn from_noop(document: &Document) -> Result<Operation> {
-        let h = document.get_i64("h")?;
-        let ts = document.get_time_stamp("ts")?;
+        let meta = OperationMeta::new(document)?;
         let o = document.get_document("o")?;
         let msg = o.get_str("msg")?;
 
         Ok(Operation::Noop {
-            id: h,
-            timestamp: timestamp_to_datetime(ts),
+            meta: meta,
             message: msg.into(),
         })
     }
 
     /// Return an insert operation for a given document.
     fn from_insert(document: &Document) -> Result<Operation> {
-        let h = document.get_i64("h")?;
-        let ts = document.get_time_stamp("ts")?;
+        let meta = OperationMeta::new(document)?;
         let ns = document.get_str("ns")?;
         let o = document.get_document("o")?;
 
         Ok(Operation::Insert {
-            id: h,
-            timestamp: timestamp_to_datetime(ts),
+            meta: meta,
             namespace: ns.into(),
             document: o.to_owned(),
         })
@@ -161,15 +319,13 @@ impl Operation {
 
     /// Return an update operation for a given document.
     fn from_update(document: &Document) -> Result<Operation> {
-        let h = document.get_i64("h")?;
-        let ts = document.get_time_stamp("ts")?;
+        let meta = OperationMeta::new(document)?;
         let ns = document.get_str("ns")?;
         let o = document.get_document("o")?;
         let o2 = document.get_document("o2")?;
 
         Ok(Operation::Update {
-            id: h,
-            timestamp: timestamp_to_datetime(ts),
+            meta: meta,
             namespace: ns.into(),
             query: o2.to_owned(),
             update: o.to_owned(),
@@ -178,14 +334,12 @@ impl Operation {
 
     /// Return a delete operation for a given document.
     fn from_delete(document: &Document) -> Result<Operation> {
-        let h = document.get_i64("h")?;
-        let ts = document.get_time_stamp("ts")?;
+        let meta = OperationMeta::new(document)?;
         let ns = document.get_str("ns")?;
         let o = document.get_document("o")?;
 
         Ok(Operation::Delete {
-            id: h,
-            timestamp: timestamp_to_datetime(ts),
+            meta: meta,
             namespace: ns.into(),
             query: o.to_owned(),
         })
@@ -195,31 +349,30 @@ impl Operation {
     ///
     /// Note that this can return either an `Operation::Command` or an `Operation::ApplyOps` when
     /// successful.
-    fn from_command(document: &Document) -> Result<Operation> {
-        let h = document.get_i64("h")?;
-        let ts = document.get_time_stamp("ts")?;
+    fn from_command(document: &Document, lenient: bool) -> Result<Operation> {
+        let meta = OperationMeta::new(document)?;
         let ns = document.get_str("ns")?;
         let o = document.get_document("o")?;
 
         match o.get_array("applyOps") {
             Ok(ops) => {
                 let operations = ops.iter()
-                                    .map(|bson| Operation::from_bson(bson))
+                                    .map(|bson| Operation::from_bson(bson, &meta, lenient))
                                     .collect::<Result<Vec<Operation>>>()?;
 
                 Ok(Operation::ApplyOps {
-                    id: h,
-                    timestamp: timestamp_to_datetime(ts),
+                    meta: meta,
                     namespace: ns.into(),
                     operations: operations,
+                    partial: o.get_bool("partialTxn").unwrap_or(false),
+                    prepare: o.get_bool("prepare").unwrap_or(false),
                 })
             }
             Err(_) => {
                 Ok(Operation::Command {
-                    id: h,
-                    timestamp: timestamp_to_datetime(ts),
+                    meta: meta,
                     namespace: ns.into(),
-                    command: o.to_owned(),
+                    command: CommandKind::new(o)?,
                 })
             }
         }
@@ -229,69 +382,105 @@ impl Operation {
 impl fmt::Display for Operation {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Operation::Noop { id, timestamp, ref message } => {
-                write!(f, "No-op #{} at {}: {}", id, timestamp, message)
+            Operation::Noop { ref meta, ref message } => {
+                write!(f, "No-op{} at {}: {}", Id(meta.id), meta.optime, message)
             }
-            Operation::Insert { id, timestamp, ref namespace, ref document } => {
+            Operation::Insert { ref meta, ref namespace, ref document } => {
                 write!(f,
-                       "Insert #{} into {} at {}: {}",
-                       id,
+                       "Insert{} into {} at {}: {}",
+                       Id(meta.id),
                        namespace,
-                       timestamp,
+                       meta.optime,
                        document)
             }
-            Operation::Update { id, timestamp, ref namespace, ref query, ref update } => {
+            Operation::Update { ref meta, ref namespace, ref query, ref update } => {
                 write!(f,
-                       "Update #{} {} with {} at {}: {}",
-                       id,
+                       "Update{} {} with {} at {}: {}",
+                       Id(meta.id),
                        namespace,
                        query,
-                       timestamp,
+                       meta.optime,
                        update)
             }
-            Operation::Delete { id, timestamp, ref namespace, ref query } => {
+            Operation::Delete { ref meta, ref namespace, ref query } => {
                 write!(f,
-                       "Delete #{} from {} at {}: {}",
-                       id,
+                       "Delete{} from {} at {}: {}",
+                       Id(meta.id),
                        namespace,
-                       timestamp,
+                       meta.optime,
                        query)
             }
-            Operation::Command { id, timestamp, ref namespace, ref command } => {
+            Operation::Command { ref meta, ref namespace, ref command } => {
                 write!(f,
-                       "Command #{} {} at {}: {}",
-                       id,
+                       "Command{} {} at {}: {}",
+                       Id(meta.id),
                        namespace,
-                       timestamp,
+                       meta.optime,
                        command)
             }
-            Operation::ApplyOps { id, timestamp, ref namespace, ref operations } => {
+            Operation::ApplyOps { ref meta, ref namespace, ref operations, .. } => {
                 write!(f,
-                       "ApplyOps #{} {} at {}: {} operations",
-                       id,
+                       "ApplyOps{} {} at {}: {} operations",
+                       Id(meta.id),
                        namespace,
-                       timestamp,
+                       meta.optime,
                        operations.len())
             }
+            Operation::Transaction { ref meta, ref operations } => {
+                write!(f,
+                       "Transaction{} at {}: {} operations",
+                       Id(meta.id),
+                       meta.optime,
+                       operations.len())
+            }
+            Operation::Unknown { ref meta, ref raw } => {
+                write!(f, "Unknown{} at {}: {}", Id(meta.id), meta.optime, raw)
+            }
         }
     }
 }
 
-/// Convert a BSON timestamp into a UTC `DateTime`.
-fn timestamp_to_datetime(timestamp: i64) -> DateTime<UTC> {
-    let seconds = timestamp >> 32;
-    let nanoseconds = ((timestamp & 0xFFFFFFFF) * 1000000) as u32;
+/// Returns every given operation kept by the given predicate.
+fn retain_all<F>(operations: Vec<Operation>, keep: &F, transactions: bool) -> Vec<Operation>
+    where F: Fn(&Operation) -> bool
+{
+    operations.into_iter().filter_map(|operation| operation.retain(keep, transactions)).collect()
+}
+
+/// Formats an optional operation identifier for display.
+struct Id(Option<i64>);
 
-    UTC.timestamp(seconds, nanoseconds)
+impl fmt::Display for Id {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(id) => write!(f, " #{}", id),
+            None => Ok(()),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use Error;
+    use {CommandKind, Error, OperationMeta, OpTime};
     use bson::{Bson, ValueAccessError};
     use chrono::{UTC, TimeZone};
     use super::Operation;
 
+    fn meta(id: Option<i64>, optime: OpTime) -> OperationMeta {
+        OperationMeta {
+            id: id,
+            optime: optime,
+            version: 2,
+            wall_time: None,
+            collection_uuid: None,
+            session_id: None,
+            txn_number: None,
+            statement_ids: vec![],
+            prev_optime: None,
+            from_migrate: false,
+        }
+    }
+
     #[test]
     fn operation_converts_noops() {
         let doc = doc! {
@@ -308,8 +497,7 @@ mod tests {
 
         assert_eq!(operation,
                    Operation::Noop {
-                       id: -2135725856567446411i64,
-                       timestamp: UTC.timestamp(1479419535, 0),
+                       meta: meta(Some(-2135725856567446411i64), OpTime::new(1479419535, 0, None)),
                        message: "initiating set".into(),
                    });
     }
@@ -330,13 +518,93 @@ mod tests {
 
         assert_eq!(operation,
                    Operation::Insert {
-                       id: -1742072865587022793i64,
-                       timestamp: UTC.timestamp(1479561394, 0),
+                       meta: meta(Some(-1742072865587022793i64), OpTime::new(1479561394, 0, None)),
                        namespace: "foo.bar".into(),
                        document: doc! { "foo" => "bar" },
                    });
     }
 
+    #[test]
+    fn operation_preserves_increments_and_terms() {
+        let doc = doc! {
+            "ts" => (Bson::TimeStamp(1479561394 << 32 | 7)),
+            "t" => 3i64,
+            "h" => (-1742072865587022793i64),
+            "v" => 2,
+            "op" => "i",
+            "ns" => "foo.bar",
+            "o" => {
+                "foo" => "bar"
+            }
+        };
+        let operation = Operation::new(&doc).unwrap();
+
+        assert_eq!(operation.optime(), OpTime::new(1479561394, 7, Some(3)));
+        assert_eq!(operation.timestamp(), UTC.timestamp(1479561394, 0));
+    }
+
+    #[test]
+    fn operation_converts_entries_without_hashes() {
+        let doc = doc! {
+            "ts" => (Bson::TimeStamp(1479561394 << 32 | 1)),
+            "t" => 1i64,
+            "v" => 2,
+            "op" => "i",
+            "ns" => "foo.bar",
+            "o" => {
+                "foo" => "bar"
+            }
+        };
+        let operation = Operation::new(&doc).unwrap();
+
+        assert_eq!(operation,
+                   Operation::Insert {
+                       meta: meta(None, OpTime::new(1479561394, 1, Some(1))),
+                       namespace: "foo.bar".into(),
+                       document: doc! { "foo" => "bar" },
+                   });
+    }
+
+    #[test]
+    fn operation_treats_zero_hashes_as_missing() {
+        let doc = doc! {
+            "ts" => (Bson::TimeStamp(1479561394 << 32 | 1)),
+            "t" => 1i64,
+            "h" => 0i64,
+            "v" => 2,
+            "op" => "d",
+            "ns" => "foo.bar",
+            "o" => {
+                "_id" => 1
+            }
+        };
+        let operation = Operation::new(&doc).unwrap();
+
+        match operation {
+            Operation::Delete { meta, .. } => assert_eq!(meta.id, None),
+            _ => panic!("Expected delete."),
+        }
+    }
+
+    #[test]
+    fn operation_requires_hashes_for_version_one_entries() {
+        let doc = doc! {
+            "ts" => (Bson::TimeStamp(1479561394 << 32)),
+            "v" => 1,
+            "op" => "i",
+            "ns" => "foo.bar",
+            "o" => {
+                "foo" => "bar"
+            }
+        };
+        let operation = Operation::new(&doc);
+
+        match operation {
+            Err(Error::MissingField(err)) => assert_eq!(err, ValueAccessError::NotPresent),
+            _ => panic!("Expected missing field."),
+        }
+    }
+
     #[test]
     fn operation_converts_updates() {
         let doc = doc! {
@@ -358,8 +626,7 @@ mod tests {
 
         assert_eq!(operation,
                    Operation::Update {
-                       id: 3511341713062188019i64,
-                       timestamp: UTC.timestamp(1479561033, 0),
+                       meta: meta(Some(3511341713062188019i64), OpTime::new(1479561033, 0, None)),
                        namespace: "foo.bar".into(),
                        query: doc! { "_id" => 1 },
                        update: doc! { "$set" => { "foo" => "baz" } },
@@ -382,8 +649,7 @@ mod tests {
 
         assert_eq!(operation,
                    Operation::Delete {
-                       id: -5457382347563537847i64,
-                       timestamp: UTC.timestamp(1479421186, 0),
+                       meta: meta(Some(-5457382347563537847i64), OpTime::new(1479421186, 0, None)),
                        namespace: "foo.bar".into(),
                        query: doc! { "_id" => 1 },
                    });
@@ -405,10 +671,12 @@ mod tests {
 
         assert_eq!(operation,
                    Operation::Command {
-                       id: -7222343681970774929i64,
-                       timestamp: UTC.timestamp(1479553955, 0),
+                       meta: meta(Some(-7222343681970774929i64), OpTime::new(1479553955, 0, None)),
                        namespace: "test.$cmd".into(),
-                       command: doc! { "create" => "foo" },
+                       command: CommandKind::Create {
+                           collection: "foo".into(),
+                           options: doc! {},
+                       },
                    });
     }
 
@@ -423,6 +691,64 @@ mod tests {
         }
     }
 
+    #[test]
+    fn operation_preserves_unknown_operations_leniently() {
+        let doc = doc! {
+            "ts" => (Bson::TimeStamp(1479561394 << 32)),
+            "h" => (-1742072865587022793i64),
+            "v" => 2,
+            "op" => "db",
+            "ns" => "foo"
+        };
+        let operation = Operation::new_lenient(&doc).unwrap();
+
+        assert_eq!(operation,
+                   Operation::Unknown {
+                       meta: meta(Some(-1742072865587022793i64), OpTime::new(1479561394, 0, None)),
+                       raw: doc.clone(),
+                   });
+    }
+
+    #[test]
+    fn operation_preserves_unknown_nested_operations_leniently() {
+        let doc = doc! {
+            "ts" => (Bson::TimeStamp(1483789052 << 32)),
+            "v" => 2,
+            "op" => "c",
+            "ns" => "foo.$cmd",
+            "o" => {
+                "applyOps" => [
+                    {
+                        "op" => "x",
+                        "ns" => "foo.bar"
+                    }
+                ]
+            }
+        };
+        let operation = Operation::new_lenient(&doc).unwrap();
+
+        match operation {
+            Operation::ApplyOps { operations, .. } => {
+                match operations[0] {
+                    Operation::Unknown { .. } => {}
+                    _ => panic!("Expected unknown operation."),
+                }
+            }
+            _ => panic!("Expected applyOps."),
+        }
+    }
+
+    #[test]
+    fn operation_returns_missing_positions_leniently() {
+        let doc = doc! { "op" => "x" };
+        let operation = Operation::new_lenient(&doc);
+
+        match operation {
+            Err(Error::MissingField(err)) => assert_eq!(err, ValueAccessError::NotPresent),
+            _ => panic!("Expected missing field."),
+        }
+    }
+
     #[test]
     fn operation_returns_missing_fields() {
         let doc = doc! { "foo" => "bar" };
@@ -462,15 +788,53 @@ mod tests {
 
         assert_eq!(operation,
                    Operation::ApplyOps {
-                       id: -3262249347345468996i64,
-                       timestamp: UTC.timestamp(1483789052, 0),
+                       meta: meta(Some(-3262249347345468996i64), OpTime::new(1483789052, 0, None)),
                        namespace: "foo.$cmd".into(),
                        operations: vec![Operation::Insert {
-                                            id: -1742072865587022793i64,
-                                            timestamp: UTC.timestamp(1479561394, 0),
+                                            meta: meta(Some(-1742072865587022793i64),
+                                                       OpTime::new(1479561394, 0, Some(2))),
                                             namespace: "foo.bar".into(),
                                             document: doc! { "_id" => 1, "foo" => "bar" },
                                         }],
+                       partial: false,
+                       prepare: false,
                    });
     }
+
+    #[test]
+    fn operation_converts_apply_ops_without_nested_positions() {
+        let doc = doc! {
+            "lsid" => {
+                "id" => (Bson::Binary(::bson::spec::BinarySubtype::Uuid, vec![1, 2, 3]))
+            },
+            "txnNumber" => 1i64,
+            "op" => "c",
+            "ns" => "admin.$cmd",
+            "o" => {
+                "applyOps" => [
+                    {
+                        "op" => "i",
+                        "ns" => "foo.bar",
+                        "o" => {
+                            "_id" => 1
+                        }
+                    }
+                ],
+                "partialTxn" => true
+            },
+            "ts" => (Bson::TimeStamp(1483789052 << 32 | 4)),
+            "t" => 2i64,
+            "v" => 2i64
+        };
+        let operation = Operation::new(&doc).unwrap();
+
+        match operation {
+            Operation::ApplyOps { operations, partial, prepare, .. } => {
+                assert_eq!(operations[0].optime(), OpTime::new(1483789052, 4, Some(2)));
+                assert!(partial);
+                assert!(!prepare);
+            }
+            _ => panic!("Expected applyOps."),
+        }
+    }
 }
@@ -1,38 +1,81 @@
 //! The oplog module is responsible for building an iterator over a MongoDB replica set oplog with
 //! any optional filtering criteria applied.
 
+use std::fs::File;
+use std::io::Read;
+use std::mem;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
 use bson::Document;
-use mongodb::coll::options::{FindOptions, CursorType};
-use mongodb::cursor::Cursor;
-use mongodb::db::ThreadedDatabase;
-use mongodb::{Client, ThreadedClient};
+use chrono::{DateTime, UTC};
+use mongodb::Client;
+use mongodb::common::ReadPreference;
 
-use {Operation, Result};
+use {Backoff, BsonSource, Checkpoint, CursorSource, Error, Operation, OperationKind, OplogEvents,
+     OplogHandle, OplogSource, OpTime, Result, Transactions};
+use kind::Kinds;
+use matcher;
+use namespace::Namespaces;
+use query::{Query, Start};
+#[cfg(feature = "async")]
+use OplogStream;
 
 /// Oplog represents a MongoDB replica set oplog.
 ///
 /// It implements the `Iterator` trait so it can be iterated over, yielding successive `Operation`s
-/// as they are read from the server. This will effectively iterate forever as it will await new
-/// operations.
+/// as they are read from the server. Unless built to be `finite` or with an `until` position, this
+/// will effectively iterate forever as it will await new operations.
 ///
 /// Any errors raised while tailing the oplog (e.g. a connectivity issue) will cause the iteration
-/// to end.
-pub struct Oplog {
-    /// The internal MongoDB cursor for the current position in the oplog.
-    cursor: Cursor,
+/// to end unless it was built to `reconnect`. Use `try_iter` to receive these errors instead.
+///
+/// The oplog is read from a MongoDB server by default but can be read from any `OplogSource`,
+/// e.g. to test code using it without a live replica set.
+pub struct Oplog<S = CursorSource> {
+    /// The source of raw oplog documents.
+    source: S,
+    /// The query used to read the oplog.
+    query: Query,
+    /// The position of the last entry read from the source.
+    last_optime: Option<OpTime>,
+    /// Whether iteration has passed `until` or reached the end of a finite oplog.
+    finished: bool,
+    /// How to retry resuming after the source is lost or `None` to end iteration instead.
+    backoff: Option<Backoff>,
+    /// Whether to yield unconvertible entries as `Operation::Unknown` rather than end iteration.
+    lenient: bool,
+    /// How long to read without an operation before yielding an idle event, if at all.
+    idle_timeout: Option<Duration>,
+    /// Whether to keep the entries needed to reassemble transactions regardless of any filters.
+    transactions: bool,
 }
 
-impl Iterator for Oplog {
+impl<S: OplogSource> Iterator for Oplog<S> {
     type Item = Operation;
 
     fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            match self.cursor.next() {
-                Some(Ok(document)) => return Operation::new(&document).ok(),
-                Some(Err(_)) => return None,
-                None => continue,
-            }
-        }
+        self.next_result().and_then(|result| result.ok())
+    }
+}
+
+/// An iterator over an `Oplog` yielding the result of reading each operation.
+///
+/// Unlike iterating over the `Oplog` directly, any errors raised while reading or converting an
+/// operation are yielded rather than silently ending the iteration, so the caller can choose
+/// whether to skip them, log them or stop.
+///
+/// This is created by `Oplog::try_iter`.
+pub struct TryOplog<'a, S: 'a = CursorSource> {
+    oplog: &'a mut Oplog<S>,
+}
+
+impl<'a, S: OplogSource> Iterator for TryOplog<'a, S> {
+    type Item = Result<Operation>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.oplog.next_result()
     }
 }
 
@@ -60,16 +103,407 @@ impl Oplog {
     }
 }
 
+impl<R: Read> Oplog<BsonSource<R>> {
+    /// Returns a new `Oplog` reading every operation from a stream of concatenated BSON documents,
+    /// e.g. the `oplog.bson` written by `mongodump --oplog`.
+    ///
+    /// Iteration ends at the end of the stream.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::io;
+    /// use oplog::Oplog;
+    ///
+    /// for operation in Oplog::from_reader(io::stdin()) {
+    ///     // Do something with operation...
+    /// }
+    /// ```
+    pub fn from_reader(reader: R) -> Oplog<BsonSource<R>> {
+        Oplog::from_source(BsonSource::new(reader))
+    }
+}
+
+impl Oplog<BsonSource<File>> {
+    /// Returns a new `Oplog` reading every operation from a file of concatenated BSON documents,
+    /// e.g. the `oplog.bson` written by `mongodump --oplog`.
+    ///
+    /// Returns an `Error::Io` if the file cannot be opened.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use oplog::Oplog;
+    ///
+    /// if let Ok(oplog) = Oplog::from_path("dump/oplog.bson") {
+    ///     for operation in oplog {
+    ///         // Do something with operation...
+    ///     }
+    /// }
+    /// ```
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Oplog<BsonSource<File>>> {
+        Ok(Oplog::from_reader(File::open(path)?))
+    }
+}
+
+impl<S: OplogSource> Oplog<S> {
+    /// Returns a new `Oplog` reading every operation from the given source.
+    ///
+    /// Use `OplogBuilder::build_from` to apply any filters.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use]
+    /// # extern crate bson;
+    /// # extern crate oplog;
+    /// use bson::Bson;
+    /// use oplog::Oplog;
+    ///
+    /// # fn main() {
+    /// let documents = vec![doc! {
+    ///     "ts" => (Bson::TimeStamp(1479419535 << 32)),
+    ///     "h" => (-2135725856567446411i64),
+    ///     "v" => 2,
+    ///     "op" => "i",
+    ///     "ns" => "foo.bar",
+    ///     "o" => { "foo" => "bar" }
+    /// }];
+    /// let oplog = Oplog::from_source(documents.into_iter());
+    ///
+    /// assert_eq!(oplog.count(), 1);
+    /// # }
+    /// ```
+    pub fn from_source(source: S) -> Oplog<S> {
+        Oplog::with_query(source, Query::default(), None, false)
+    }
+
+    /// Returns a new `Oplog` reading operations matching the given query from the given source.
+    fn with_query(source: S, query: Query, backoff: Option<Backoff>, lenient: bool) -> Oplog<S> {
+        Oplog {
+            source: source,
+            query: query,
+            last_optime: None,
+            finished: false,
+            backoff: backoff,
+            lenient: lenient,
+            idle_timeout: None,
+            transactions: false,
+        }
+    }
+
+    /// Returns an iterator yielding each committed multi-document transaction as a single
+    /// `Operation::Transaction` rather than its individual oplog entries.
+    ///
+    /// See `Transactions` for more details.
+    pub fn transactions(mut self) -> Transactions<Oplog<S>> {
+        self.keep_transactions();
+
+        Transactions::new(self)
+    }
+
+    /// Set whether to yield unconvertible entries as `Operation::Unknown`, returning the previous
+    /// setting.
+    pub(crate) fn replace_lenient(&mut self, lenient: bool) -> bool {
+        mem::replace(&mut self.lenient, lenient)
+    }
+
+    /// Keep the entries needed to reassemble transactions even if they are otherwise filtered out.
+    pub(crate) fn keep_transactions(&mut self) {
+        self.transactions = true;
+    }
+
+    /// Returns an iterator yielding the result of reading each operation so that errors can be
+    /// handled rather than ending iteration.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// use mongodb::{Client, ThreadedClient};
+    /// use oplog::Oplog;
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    ///
+    /// if let Ok(mut oplog) = Oplog::new(&client) {
+    ///     for result in oplog.try_iter() {
+    ///         match result {
+    ///             Ok(operation) => println!("{}", operation),
+    ///             Err(err) => println!("Failed to read operation: {}", err),
+    ///         }
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn try_iter(&mut self) -> TryOplog<'_, S> {
+        TryOplog { oplog: self }
+    }
+
+    /// Returns an iterator yielding the result of reading each operation as an `OplogEvent` and
+    /// an idle event whenever no operation is read within the idle timeout set with
+    /// `OplogBuilder::idle_timeout`.
+    ///
+    /// See `OplogEvents` for more details.
+    pub fn events(&mut self) -> OplogEvents<'_, S> {
+        let timeout = self.idle_timeout;
+
+        OplogEvents::new(self, timeout)
+    }
+
+    /// Tail this oplog on a new thread, buffering up to the given number of operations.
+    ///
+    /// See `OplogHandle` for more details.
+    pub fn spawn(self, capacity: usize) -> OplogHandle
+        where S: Send + 'static
+    {
+        OplogHandle::spawn(self, capacity)
+    }
+
+    /// Returns an asynchronous `Stream` of the result of reading each operation, buffering up to
+    /// the given number of operations.
+    ///
+    /// The oplog is read on a dedicated OS thread, as with `Oplog::spawn`, until the stream is
+    /// dropped so each stream still costs a thread. This requires the `async` feature.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate futures;
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// use futures::{future, StreamExt};
+    /// use mongodb::{Client, ThreadedClient};
+    /// use oplog::Oplog;
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    ///
+    /// if let Ok(oplog) = Oplog::new(&client) {
+    ///     let operations = oplog.into_stream(16).for_each(|result| {
+    ///         // Do something with result...
+    ///         future::ready(())
+    ///     });
+    ///
+    ///     // Run operations on an executor...
+    /// }
+    /// # }
+    /// ```
+    #[cfg(feature = "async")]
+    pub fn into_stream(self, capacity: usize) -> OplogStream
+        where S: Send + 'static
+    {
+        OplogStream::new(self, capacity)
+    }
+
+    /// Returns the result of reading the next operation, awaiting one if necessary.
+    fn next_result(&mut self) -> Option<Result<Operation>> {
+        self.next_entry().map(|result| result.map(|(operation, _)| operation))
+    }
+
+    /// Returns the result of reading the next operation along with its raw document, awaiting one
+    /// if necessary.
+    pub(crate) fn next_entry(&mut self) -> Option<Result<(Operation, Document)>> {
+        loop {
+            match self.step() {
+                Step::Entry(entry) => return Some(*entry),
+                Step::Pending => continue,
+                Step::End => return None,
+            }
+        }
+    }
+
+    /// Make a single attempt to read the next operation along with its raw document.
+    ///
+    /// This only blocks for as long as the source does before returning `Step::Pending` so that
+    /// callers can interleave other work (e.g. checking for cancellation) while awaiting new
+    /// operations.
+    pub(crate) fn step(&mut self) -> Step {
+        if self.finished {
+            return Step::End;
+        }
+
+        match self.source.next_document() {
+            Some(Ok(document)) => {
+                if let Ok(optime) = OpTime::from_document(&document) {
+                    if self.is_past_until(optime) {
+                        self.finished = true;
+
+                        return Step::End;
+                    }
+
+                    if self.is_before_start(optime) {
+                        return Step::Pending;
+                    }
+
+                    self.last_optime = Some(optime);
+                }
+
+                match self.operation(&document) {
+                    Ok(operation) => {
+                        match self.retain(operation, &document) {
+                            Some(operation) => Step::Entry(Box::new(Ok((operation, document)))),
+                            None => Step::Pending,
+                        }
+                    }
+                    Err(err) => Step::Entry(Box::new(Err(err))),
+                }
+            }
+            Some(Err(err)) if is_unrecoverable(&err) => {
+                self.finished = true;
+
+                Step::Entry(Box::new(Err(err)))
+            }
+            Some(Err(err)) => {
+                match self.reconnect(err) {
+                    Ok(()) => Step::Pending,
+                    Err(err) => {
+                        if is_unrecoverable(&err) {
+                            self.finished = true;
+                        }
+
+                        Step::Entry(Box::new(Err(err)))
+                    }
+                }
+            }
+            None if self.source.is_tailing() && self.source.is_closed() => {
+                match self.reconnect(Error::CursorClosed) {
+                    Ok(()) => Step::Pending,
+                    Err(err) => {
+                        self.finished = true;
+
+                        Step::Entry(Box::new(Err(err)))
+                    }
+                }
+            }
+            None if self.source.is_tailing() => Step::Pending,
+            None => {
+                self.finished = true;
+
+                Step::End
+            }
+        }
+    }
+
+    /// Returns the position of the last entry read from the source, including any filtered out.
+    pub(crate) fn last_optime(&self) -> Option<OpTime> {
+        self.last_optime
+    }
+
+    /// Returns whether the given position is before the position from which to start.
+    ///
+    /// The server only returns operations from the start position but other sources may not.
+    fn is_before_start(&self, optime: OpTime) -> bool {
+        match self.query.start {
+            Some(Start::Since(since)) => optime.comparable_to(since) < since,
+            Some(Start::After(after)) => optime.comparable_to(after) <= after,
+            None => false,
+        }
+    }
+
+    /// Returns whether the given position is after the position at which iteration ends.
+    ///
+    /// Only timestamps are compared if the `until` position has no term so that it is inclusive.
+    fn is_past_until(&self, optime: OpTime) -> bool {
+        match self.query.until {
+            Some(until) => optime.comparable_to(until) > until,
+            None => false,
+        }
+    }
+
+    /// Resume a lost source after the last entry read, retrying with the configured backoff.
+    ///
+    /// Returns the last error encountered if the oplog was not built to reconnect, the source
+    /// cannot be resumed or every attempt failed.
+    fn reconnect(&mut self, err: Error) -> Result<()> {
+        let backoff = match self.backoff {
+            Some(backoff) => backoff,
+            None => return Err(err),
+        };
+        let mut err = err;
+        let mut attempts = 0;
+
+        while backoff.retry(attempts) {
+            thread::sleep(backoff.delay(attempts));
+            attempts += 1;
+
+            match self.source.resume(self.last_optime) {
+                Some(Ok(())) => return Ok(()),
+                Some(Err(next_err)) => {
+                    if is_unrecoverable(&next_err) {
+                        return Err(next_err);
+                    }
+
+                    err = next_err;
+                }
+                None => break,
+            }
+        }
+
+        Err(err)
+    }
+
+    /// Returns the given operation without any nested operations excluded by the query or `None`
+    /// if it is excluded entirely.
+    ///
+    /// No-ops only read as heartbeats despite a user's filter are excluded too, i.e. those the
+    /// filter is known not to match. Those it cannot be evaluated against on the client are kept
+    /// rather than risk dropping one it matches.
+    fn retain(&self, operation: Operation, document: &Document) -> Option<Operation> {
+        if self.query.heartbeats && operation.kind() == OperationKind::Noop {
+            if let Some(ref filter) = self.query.filter {
+                if matcher::matches(filter, document) == Some(false) {
+                    return None;
+                }
+            }
+        }
+
+        self.query
+            .namespaces
+            .retain(operation, self.transactions)
+            .and_then(|operation| self.query.kinds.retain(operation, self.transactions))
+    }
+
+    /// Returns the operation for a document read from the oplog.
+    fn operation(&self, document: &Document) -> Result<Operation> {
+        if self.lenient {
+            Operation::new_lenient(document)
+        } else {
+            Operation::new(document)
+        }
+    }
+}
+
+/// The outcome of a single attempt to read an operation from an `Oplog`.
+pub(crate) enum Step {
+    /// An operation along with its raw document or the error raised reading it.
+    Entry(Box<Result<(Operation, Document)>>),
+    /// No operation is available yet or the entry read was filtered out.
+    Pending,
+    /// Iteration has ended.
+    End,
+}
+
 /// A builder for an `Oplog`.
 ///
 /// This builder enables configuring a filter on the oplog so that only operations matching a given
 /// criteria are returned (e.g. to set a start time or filter out unwanted operation types).
 ///
+/// The cursor can be tuned with `batch_size`, `max_await_time`, `no_cursor_timeout`, `projection`
+/// and `read_preference`.
+///
 /// The lifetime `'a` refers to the lifetime of the MongoDB client.
 #[derive(Clone)]
 pub struct OplogBuilder<'a> {
     client: &'a Client,
-    filter: Option<Document>,
+    query: Query,
+    namespaces: Vec<String>,
+    excluded_namespaces: Vec<String>,
+    backoff: Option<Backoff>,
+    lenient: bool,
+    checkpoint: Option<&'a dyn Checkpoint>,
+    idle_timeout: Option<Duration>,
 }
 
 impl<'a> OplogBuilder<'a> {
@@ -96,26 +530,133 @@ impl<'a> OplogBuilder<'a> {
     pub fn new(client: &'a Client) -> OplogBuilder<'a> {
         OplogBuilder {
             client: client,
-            filter: None,
+            query: Query { no_cursor_timeout: true, ..Query::default() },
+            namespaces: Vec::new(),
+            excluded_namespaces: Vec::new(),
+            backoff: None,
+            lenient: false,
+            checkpoint: None,
+            idle_timeout: None,
         }
     }
 
     /// Executes the query and builds the `Oplog`.
+    ///
+    /// Returns an `Error::InvalidNamespace` if any namespace is an invalid regular expression.
     pub fn build(&self) -> Result<Oplog> {
-        let coll = self.client.db("local").collection("oplog.rs");
+        let query = self.query()?;
+        let source = CursorSource::new(self.client, query.clone())?;
+
+        Ok(self.oplog(source, query))
+    }
+
+    /// Builds the `Oplog` reading from the given source rather than the server, e.g. to test code
+    /// using it without a live replica set.
+    ///
+    /// Every option other than `filter` is applied as the source is read. A `filter` can only be
+    /// applied by the server so this returns an `Error::UnsupportedFilter` if one is set rather
+    /// than silently yielding operations it would have excluded.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate bson;
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// use bson::Document;
+    /// use mongodb::{Client, ThreadedClient};
+    /// use oplog::{OperationKind, OplogBuilder};
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    /// let documents: Vec<Document> = Vec::new();
+    /// let builder = OplogBuilder::new(&client).kinds(&[OperationKind::Insert]).clone();
+    ///
+    /// if let Ok(oplog) = builder.build_from(documents.into_iter()) {
+    ///     // Do something with inserts from documents.
+    /// }
+    /// # }
+    /// ```
+    pub fn build_from<S: OplogSource>(&self, source: S) -> Result<Oplog<S>> {
+        let query = self.query()?;
+        check_client_side(&query)?;
+
+        Ok(self.oplog(source, query))
+    }
+
+    /// Executes the query and tails the `Oplog` on a new thread, buffering up to the given number
+    /// of operations.
+    ///
+    /// See `OplogHandle` for more details.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// use mongodb::{Client, ThreadedClient};
+    /// use oplog::OplogBuilder;
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    ///
+    /// if let Ok(handle) = OplogBuilder::new(&client).spawn(100) {
+    ///     for result in handle {
+    ///         // Do something with result...
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn spawn(&self, capacity: usize) -> Result<OplogHandle> {
+        Ok(self.build()?.spawn(capacity))
+    }
+
+    /// Executes the query and builds the `Oplog` as an asynchronous `Stream`, buffering up to the
+    /// given number of operations.
+    ///
+    /// See `Oplog::into_stream` for more details. This requires the `async` feature.
+    #[cfg(feature = "async")]
+    pub fn build_stream(&self, capacity: usize) -> Result<OplogStream> {
+        Ok(self.build()?.into_stream(capacity))
+    }
+
+    /// Executes the query, reading no-ops regardless of any other criteria, and builds the
+    /// `Oplog`.
+    pub(crate) fn build_with_heartbeats(&self) -> Result<Oplog> {
+        let mut query = self.query()?;
+        query.heartbeats = true;
+        let source = CursorSource::new(self.client, query.clone())?;
+
+        Ok(self.oplog(source, query))
+    }
+
+    /// Returns a new `Oplog` reading the given source with the given query and every other option.
+    fn oplog<S: OplogSource>(&self, source: S, query: Query) -> Oplog<S> {
+        let mut oplog = Oplog::with_query(source, query, self.backoff, self.lenient);
+        oplog.idle_timeout = self.idle_timeout;
+
+        oplog
+    }
 
-        let mut opts = FindOptions::new();
-        opts.cursor_type = CursorType::TailableAwait;
-        opts.no_cursor_timeout = true;
+    /// Returns the query for the oplog including its compiled namespaces and any start position
+    /// loaded from a checkpoint.
+    fn query(&self) -> Result<Query> {
+        let mut query = self.query.clone();
+        query.namespaces = Namespaces::new(&self.namespaces, &self.excluded_namespaces)?;
 
-        let cursor = coll.find(self.filter.clone(), Some(opts))?;
+        if let Some(checkpoint) = self.checkpoint {
+            if let Some(optime) = checkpoint.load()? {
+                query.start = Some(Start::After(optime));
+            }
+        }
 
-        Ok(Oplog { cursor: cursor })
+        Ok(query)
     }
 
     /// Provide an optional filter for the oplog.
     ///
-    /// This is empty by default so all operations are returned.
+    /// This is empty by default so all operations are returned. It is combined with any start
+    /// position set with `since` or `after` rather than replacing it.
     ///
     /// # Example
     ///
@@ -137,7 +678,1004 @@ impl<'a> OplogBuilder<'a> {
     /// ```
     #[allow(dead_code)]
     pub fn filter(&mut self, filter: Option<Document>) -> &mut OplogBuilder<'a> {
-        self.filter = filter;
+        self.query.filter = filter;
+        self
+    }
+
+    /// Only read operations in the given namespaces.
+    ///
+    /// Each namespace is either exact (e.g. `app.users`), every collection in a database (e.g.
+    /// `billing.*`) or a regular expression between slashes (e.g. `/^app\\.logs_/`). Commands are
+    /// matched by the namespace they affect and the operations within an `ApplyOps` are filtered
+    /// individually.
+    ///
+    /// This is empty by default so operations in every namespace are returned.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// use mongodb::{Client, ThreadedClient};
+    /// use oplog::OplogBuilder;
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    ///
+    /// let oplog = OplogBuilder::new(&client).namespaces(&["app.users", "billing.*"]).build();
+    ///
+    /// if let Ok(oplog) = oplog {
+    ///     // Do something with operations on users or billing.
+    /// }
+    /// # }
+    /// ```
+    pub fn namespaces<S: AsRef<str>>(&mut self, namespaces: &[S]) -> &mut OplogBuilder<'a> {
+        self.namespaces = owned(namespaces);
         self
     }
+
+    /// Never read operations in the given namespaces, even if included by `namespaces`.
+    ///
+    /// Namespaces take the same form as in `namespaces`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// use mongodb::{Client, ThreadedClient};
+    /// use oplog::OplogBuilder;
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    ///
+    /// let oplog = OplogBuilder::new(&client).exclude_namespaces(&["app.sessions"]).build();
+    ///
+    /// if let Ok(oplog) = oplog {
+    ///     // Do something with operations outside of sessions.
+    /// }
+    /// # }
+    /// ```
+    pub fn exclude_namespaces<S: AsRef<str>>(&mut self, namespaces: &[S]) -> &mut OplogBuilder<'a> {
+        self.excluded_namespaces = owned(namespaces);
+        self
+    }
+
+    /// Only read operations of the given kinds.
+    ///
+    /// The operations within an `ApplyOps` are filtered individually unless `ApplyOps` is given.
+    /// This is empty by default so operations of every kind are returned.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// use mongodb::{Client, ThreadedClient};
+    /// use oplog::{OperationKind, OplogBuilder};
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    /// let kinds = [OperationKind::Insert, OperationKind::Delete];
+    ///
+    /// if let Ok(oplog) = OplogBuilder::new(&client).kinds(&kinds).build() {
+    ///     // Do something with inserts and deletes.
+    /// }
+    /// # }
+    /// ```
+    pub fn kinds(&mut self, kinds: &[OperationKind]) -> &mut OplogBuilder<'a> {
+        self.query.kinds = Kinds::new(kinds);
+        self
+    }
+
+    /// Start reading the oplog from the operation at the given position inclusively.
+    ///
+    /// This replaces any position set with `after` or `since_datetime`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// use mongodb::{Client, ThreadedClient};
+    /// use oplog::{OplogBuilder, OpTime};
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    /// let optime = OpTime::new(1479561394, 0, None);
+    ///
+    /// if let Ok(oplog) = OplogBuilder::new(&client).since(optime).build() {
+    ///     // Do something with oplog.
+    /// }
+    /// # }
+    /// ```
+    pub fn since(&mut self, optime: OpTime) -> &mut OplogBuilder<'a> {
+        self.query.start = Some(Start::Since(optime));
+        self
+    }
+
+    /// Start reading the oplog from the operation immediately after the given position, e.g. to
+    /// resume after the last operation processed.
+    ///
+    /// This replaces any position set with `since` or `since_datetime`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// use mongodb::{Client, ThreadedClient};
+    /// use oplog::{OplogBuilder, OpTime};
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    /// let optime = OpTime::new(1479561394, 1, None);
+    ///
+    /// if let Ok(oplog) = OplogBuilder::new(&client).after(optime).build() {
+    ///     // Do something with oplog.
+    /// }
+    /// # }
+    /// ```
+    pub fn after(&mut self, optime: OpTime) -> &mut OplogBuilder<'a> {
+        self.query.start = Some(Start::After(optime));
+        self
+    }
+
+    /// Resume reading the oplog after the position stored in the given checkpoint, if any.
+    ///
+    /// The checkpoint is loaded when the oplog is built and any stored position replaces any
+    /// position set with `since`, `after` or `since_datetime`, which are otherwise used as
+    /// before. Positions are not stored automatically: store the position of each operation once
+    /// it has been processed.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// use mongodb::{Client, ThreadedClient};
+    /// use oplog::{FileCheckpoint, OplogBuilder};
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    /// let checkpoint = FileCheckpoint::new("oplog.checkpoint");
+    ///
+    /// if let Ok(oplog) = OplogBuilder::new(&client).checkpoint(&checkpoint).build() {
+    ///     // Do something with oplog.
+    /// }
+    /// # }
+    /// ```
+    pub fn checkpoint(&mut self, checkpoint: &'a dyn Checkpoint) -> &mut OplogBuilder<'a> {
+        self.checkpoint = Some(checkpoint);
+        self
+    }
+
+    /// Start reading the oplog from the first operation at or after the given time.
+    ///
+    /// This replaces any position set with `since` or `after`. Times before the Unix epoch or after
+    /// the latest BSON timestamp (in 2106) are clamped to the earliest and latest positions.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate chrono;
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// use chrono::{UTC, TimeZone};
+    /// use mongodb::{Client, ThreadedClient};
+    /// use oplog::OplogBuilder;
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    /// let start = UTC.ymd(2016, 11, 19).and_hms(13, 16, 34);
+    ///
+    /// if let Ok(oplog) = OplogBuilder::new(&client).since_datetime(start).build() {
+    ///     // Do something with oplog.
+    /// }
+    /// # }
+    /// ```
+    pub fn since_datetime(&mut self, datetime: DateTime<UTC>) -> &mut OplogBuilder<'a> {
+        self.since(OpTime::new(seconds_since_epoch(datetime), 0, None))
+    }
+
+    /// End iteration after the last operation at or before the given position.
+    ///
+    /// Unless the oplog is `finite`, iteration only ends once an operation after the given position
+    /// has been read.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// use mongodb::{Client, ThreadedClient};
+    /// use oplog::{OplogBuilder, OpTime};
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    /// let optime = OpTime::new(1479561394, 0, None);
+    ///
+    /// if let Ok(oplog) = OplogBuilder::new(&client).until(optime).build() {
+    ///     // Do something with oplog.
+    /// }
+    /// # }
+    /// ```
+    pub fn until(&mut self, optime: OpTime) -> &mut OplogBuilder<'a> {
+        self.query.until = Some(optime);
+        self
+    }
+
+    /// Read the oplog without tailing it so that iteration ends once the last operation (or the
+    /// last before any `until` position) has been read, e.g. to review a window of history.
+    ///
+    /// This is disabled by default so the oplog is tailed forever.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// use mongodb::{Client, ThreadedClient};
+    /// use oplog::{OplogBuilder, OpTime};
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    /// let oplog = OplogBuilder::new(&client)
+    ///                 .since(OpTime::new(1479556800, 0, None))
+    ///                 .until(OpTime::new(1479560400, 0, None))
+    ///                 .finite(true)
+    ///                 .build();
+    ///
+    /// if let Ok(oplog) = oplog {
+    ///     // Do something with every operation between 12:00 and 13:00.
+    /// }
+    /// # }
+    /// ```
+    pub fn finite(&mut self, finite: bool) -> &mut OplogBuilder<'a> {
+        self.query.finite = finite;
+        self
+    }
+
+    /// Reconnect with the given backoff whenever the oplog's cursor is lost (e.g. due to a replica
+    /// set election or network error), resuming after the last entry read.
+    ///
+    /// This is disabled by default so iteration ends if the cursor is lost.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// use mongodb::{Client, ThreadedClient};
+    /// use oplog::{Backoff, OplogBuilder};
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    ///
+    /// if let Ok(oplog) = OplogBuilder::new(&client).reconnect(Some(Backoff::default())).build() {
+    ///     // Do something with oplog.
+    /// }
+    /// # }
+    /// ```
+    pub fn reconnect(&mut self, backoff: Option<Backoff>) -> &mut OplogBuilder<'a> {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Yield any entry that cannot be converted (e.g. of an unsupported operation type) as an
+    /// `Operation::Unknown` rather than ending iteration.
+    ///
+    /// This is disabled by default.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// use mongodb::{Client, ThreadedClient};
+    /// use oplog::OplogBuilder;
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    ///
+    /// if let Ok(oplog) = OplogBuilder::new(&client).lenient(true).build() {
+    ///     // Do something with oplog including any unknown operations.
+    /// }
+    /// # }
+    /// ```
+    pub fn lenient(&mut self, lenient: bool) -> &mut OplogBuilder<'a> {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Only yield operations once they have been committed to a majority of the replica set so
+    /// that they can never be rolled back.
+    ///
+    /// This polls the replica set status for its majority-committed position so operations are
+    /// yielded with a little more latency. This is `false` by default.
+    ///
+    /// Running `replSetGetStatus` requires the `clusterMonitor` role (or another granting its
+    /// action). If the server refuses it, iteration ends with `Error::ReplicaSetStatus` rather
+    /// than retrying.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// use mongodb::{Client, ThreadedClient};
+    /// use oplog::OplogBuilder;
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    ///
+    /// if let Ok(oplog) = OplogBuilder::new(&client).majority(true).build() {
+    ///     // Do something with oplog.
+    /// }
+    /// # }
+    /// ```
+    pub fn majority(&mut self, majority: bool) -> &mut OplogBuilder<'a> {
+        self.query.majority = majority;
+        self
+    }
+
+    /// Yield an idle event from `Oplog::events` whenever no operation is read within the given
+    /// duration.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// use std::time::Duration;
+    ///
+    /// use mongodb::{Client, ThreadedClient};
+    /// use oplog::OplogBuilder;
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    ///
+    /// let mut builder = OplogBuilder::new(&client);
+    ///
+    /// if let Ok(mut oplog) = builder.idle_timeout(Duration::from_secs(5)).build() {
+    ///     for event in oplog.events() {
+    ///         // Do something with event...
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn idle_timeout(&mut self, timeout: Duration) -> &mut OplogBuilder<'a> {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Read the oplog in batches of the given number of entries rather than the server's default.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// use mongodb::{Client, ThreadedClient};
+    /// use oplog::OplogBuilder;
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    ///
+    /// if let Ok(oplog) = OplogBuilder::new(&client).batch_size(1000).build() {
+    ///     // Do something with oplog.
+    /// }
+    /// # }
+    /// ```
+    pub fn batch_size(&mut self, batch_size: i32) -> &mut OplogBuilder<'a> {
+        self.query.batch_size = Some(batch_size);
+        self
+    }
+
+    /// Set how long the server awaits new entries before returning an empty batch while tailing.
+    ///
+    /// This is sent as the `maxTimeMS` of each `getMore` and is ignored by a `finite` oplog. The
+    /// server's default is one second.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// use std::time::Duration;
+    ///
+    /// use mongodb::{Client, ThreadedClient};
+    /// use oplog::OplogBuilder;
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    /// let max_await_time = Duration::from_secs(5);
+    ///
+    /// if let Ok(oplog) = OplogBuilder::new(&client).max_await_time(max_await_time).build() {
+    ///     // Do something with oplog.
+    /// }
+    /// # }
+    /// ```
+    pub fn max_await_time(&mut self, max_await_time: Duration) -> &mut OplogBuilder<'a> {
+        self.query.max_await_time = Some(max_await_time);
+        self
+    }
+
+    /// Set whether the server should keep the cursor open indefinitely while it is inactive
+    /// rather than timing it out.
+    ///
+    /// This is `true` by default.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// use mongodb::{Client, ThreadedClient};
+    /// use oplog::OplogBuilder;
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    ///
+    /// if let Ok(oplog) = OplogBuilder::new(&client).no_cursor_timeout(false).build() {
+    ///     // Do something with oplog.
+    /// }
+    /// # }
+    /// ```
+    pub fn no_cursor_timeout(&mut self, no_cursor_timeout: bool) -> &mut OplogBuilder<'a> {
+        self.query.no_cursor_timeout = no_cursor_timeout;
+        self
+    }
+
+    /// Only return the given fields of each entry, e.g. to avoid transferring large documents.
+    ///
+    /// Entries must still include every field needed to convert them to an `Operation` (e.g.
+    /// `ts`, `op`, `ns` and `o`) or they will end iteration unless the oplog is `lenient`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// # #[macro_use]
+    /// # extern crate bson;
+    /// use mongodb::{Client, ThreadedClient};
+    /// use oplog::OplogBuilder;
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    /// let projection = doc! { "o.payload" => 0 };
+    ///
+    /// if let Ok(oplog) = OplogBuilder::new(&client).projection(Some(projection)).build() {
+    ///     // Do something with oplog.
+    /// }
+    /// # }
+    /// ```
+    pub fn projection(&mut self, projection: Option<Document>) -> &mut OplogBuilder<'a> {
+        self.query.projection = projection;
+        self
+    }
+
+    /// Read the oplog from the members of the replica set selected by the given read preference,
+    /// including any tag sets, e.g. to tail a secondary rather than add load to the primary.
+    ///
+    /// A single member is selected each time the oplog is queried and every batch is then read
+    /// from it, so a tailing oplog only moves to another member when it reconnects.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate mongodb;
+    /// # extern crate oplog;
+    /// use mongodb::{Client, ThreadedClient};
+    /// use mongodb::common::{ReadMode, ReadPreference};
+    /// use oplog::OplogBuilder;
+    ///
+    /// # fn main() {
+    /// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+    /// let read_preference = ReadPreference::new(ReadMode::Secondary, None);
+    ///
+    /// if let Ok(oplog) = OplogBuilder::new(&client).read_preference(read_preference).build() {
+    ///     // Do something with oplog.
+    /// }
+    /// # }
+    /// ```
+    pub fn read_preference(&mut self, read_preference: ReadPreference) -> &mut OplogBuilder<'a> {
+        self.query.read_mode = Some(read_preference.mode);
+        self.query.tag_sets = read_preference.tag_sets;
+        self
+    }
+}
+
+/// Returns whether the given error means the oplog cannot be resumed, e.g. as operations have been
+/// lost or the replica set refuses to report its status, so that reading it should end rather
+/// than be retried.
+fn is_unrecoverable(err: &Error) -> bool {
+    matches!(*err,
+             Error::OplogRolledOver { .. } | Error::Rollback { .. } | Error::ReplicaSetStatus(_))
+}
+
+/// Returns an error if the given query has criteria only the server can apply.
+fn check_client_side(query: &Query) -> Result<()> {
+    if query.filter.is_some() {
+        Err(Error::UnsupportedFilter)
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns the seconds since the Unix epoch of the given time, clamped to those a BSON timestamp
+/// can represent.
+fn seconds_since_epoch(datetime: DateTime<UTC>) -> u32 {
+    datetime.timestamp().max(0).min(u32::MAX as i64) as u32
+}
+
+/// Returns owned copies of the given strings.
+fn owned<S: AsRef<str>>(strings: &[S]) -> Vec<String> {
+    strings.iter().map(|string| string.as_ref().to_owned()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::time::Duration;
+    use std::vec;
+
+    use bson::{self, Bson, Document};
+    use bson::spec::BinarySubtype;
+    use chrono::{UTC, TimeZone};
+    use {Backoff, Error, Operation, OperationKind, OplogSource, OpTime, Result};
+    use kind::Kinds;
+    use namespace::Namespaces;
+    use query::{Query, Start};
+    use super::{check_client_side, seconds_since_epoch, Oplog};
+
+    fn insert(seconds: i64, namespace: &str) -> Document {
+        doc! {
+            "ts" => (Bson::TimeStamp(seconds << 32)),
+            "v" => 2,
+            "op" => "i",
+            "ns" => namespace,
+            "o" => { "_id" => seconds }
+        }
+    }
+
+    fn noop(seconds: i64) -> Document {
+        doc! {
+            "ts" => (Bson::TimeStamp(seconds << 32)),
+            "v" => 2,
+            "op" => "n",
+            "ns" => "",
+            "o" => { "msg" => "periodic noop" }
+        }
+    }
+
+    fn transaction(seconds: i64, prev_seconds: i64, o: Document) -> Document {
+        doc! {
+            "lsid" => { "id" => (Bson::Binary(BinarySubtype::Uuid, vec![1, 2, 3])) },
+            "txnNumber" => 1i64,
+            "op" => "c",
+            "ns" => "admin.$cmd",
+            "o" => o,
+            "ts" => (Bson::TimeStamp(seconds << 32)),
+            "t" => 1i64,
+            "v" => 2i64,
+            "prevOpTime" => { "ts" => (Bson::TimeStamp(prev_seconds << 32)), "t" => 1i64 }
+        }
+    }
+
+    fn seconds(operations: Vec<Operation>) -> Vec<u32> {
+        operations.iter().map(|operation| operation.optime().seconds).collect()
+    }
+
+    /// A source that fails once after its first document and then resumes after the last
+    /// position read.
+    struct FlakySource {
+        documents: vec::IntoIter<Document>,
+        failed: bool,
+        resumed_after: Option<OpTime>,
+    }
+
+    impl OplogSource for FlakySource {
+        fn next_document(&mut self) -> Option<Result<Document>> {
+            if self.documents.len() == 1 && !self.failed {
+                self.failed = true;
+
+                return Some(Err(Error::InvalidOperation));
+            }
+
+            self.documents.next().map(Ok)
+        }
+
+        fn resume(&mut self, after: Option<OpTime>) -> Option<Result<()>> {
+            self.resumed_after = after;
+
+            Some(Ok(()))
+        }
+    }
+
+    /// A tailing source whose cursor is closed by the server once it has yielded each batch.
+    struct ClosingSource {
+        batches: vec::IntoIter<Vec<Document>>,
+        documents: vec::IntoIter<Document>,
+        resumed_after: Vec<Option<OpTime>>,
+    }
+
+    impl ClosingSource {
+        fn new(mut batches: Vec<Vec<Document>>) -> ClosingSource {
+            let documents = batches.remove(0).into_iter();
+
+            ClosingSource {
+                batches: batches.into_iter(),
+                documents: documents,
+                resumed_after: Vec::new(),
+            }
+        }
+    }
+
+    impl OplogSource for ClosingSource {
+        fn next_document(&mut self) -> Option<Result<Document>> {
+            self.documents.next().map(Ok)
+        }
+
+        fn is_tailing(&self) -> bool {
+            true
+        }
+
+        fn is_closed(&self) -> bool {
+            self.documents.len() == 0
+        }
+
+        fn resume(&mut self, after: Option<OpTime>) -> Option<Result<()>> {
+            self.resumed_after.push(after);
+            self.batches.next().map(|batch| self.documents = batch.into_iter()).map(Ok)
+        }
+    }
+
+    /// A tailing source whose cursor is either closed or fails once it has yielded its documents
+    /// and which then fails to resume with the given error.
+    struct RejectingSource {
+        documents: vec::IntoIter<Document>,
+        closing: bool,
+        error: Option<Error>,
+        resumes: usize,
+    }
+
+    impl OplogSource for RejectingSource {
+        fn next_document(&mut self) -> Option<Result<Document>> {
+            match self.documents.next() {
+                Some(document) => Some(Ok(document)),
+                None if self.closing => None,
+                None => Some(Err(Error::InvalidOperation)),
+            }
+        }
+
+        fn is_tailing(&self) -> bool {
+            true
+        }
+
+        fn is_closed(&self) -> bool {
+            self.closing && self.documents.len() == 0
+        }
+
+        fn resume(&mut self, _after: Option<OpTime>) -> Option<Result<()>> {
+            self.resumes += 1;
+
+            Some(Err(self.error.take().unwrap_or(Error::InvalidOperation)))
+        }
+    }
+
+    #[test]
+    fn oplog_reads_every_document_from_a_source() {
+        let documents = vec![insert(1, "foo.bar"), insert(2, "foo.baz")];
+        let oplog = Oplog::from_source(documents.into_iter());
+
+        assert_eq!(seconds(oplog.collect()), vec![1, 2]);
+    }
+
+    #[test]
+    fn oplog_reads_operations_from_bson_streams() {
+        let mut bytes = Vec::new();
+        bson::encode_document(&mut bytes, &insert(1, "foo.bar")).unwrap();
+        bson::encode_document(&mut bytes, &insert(2, "foo.bar")).unwrap();
+        let oplog = Oplog::from_reader(Cursor::new(bytes));
+
+        assert_eq!(seconds(oplog.collect()), vec![1, 2]);
+    }
+
+    #[test]
+    fn oplog_returns_errors_for_missing_files() {
+        match Oplog::from_path("missing/oplog.bson") {
+            Err(Error::Io(_)) => {}
+            _ => panic!("Expected I/O error."),
+        }
+    }
+
+    #[test]
+    fn oplog_applies_filters_to_a_source() {
+        let query = Query {
+            start: Some(Start::After(OpTime::new(1, 0, None))),
+            until: Some(OpTime::new(3, 0, None)),
+            kinds: Kinds::new(&[OperationKind::Insert]),
+            namespaces: Namespaces::new(&["foo.bar"], &[]).unwrap(),
+            ..Query::default()
+        };
+        let documents = vec![insert(1, "foo.bar"),
+                             insert(2, "foo.bar"),
+                             insert(2, "foo.baz"),
+                             insert(3, "foo.bar"),
+                             insert(4, "foo.bar")];
+        let oplog = Oplog::with_query(documents.into_iter(), query, None, false);
+
+        assert_eq!(seconds(oplog.collect()), vec![2, 3]);
+    }
+
+    #[test]
+    fn oplog_does_not_yield_heartbeats_outside_filters() {
+        let query = Query {
+            filter: Some(doc! { "op" => "i" }),
+            heartbeats: true,
+            ..Query::default()
+        };
+        let documents = vec![insert(1, "foo.bar"), noop(2), insert(3, "foo.bar")];
+        let oplog = Oplog::with_query(documents.into_iter(), query, None, false);
+
+        assert_eq!(seconds(oplog.collect()), vec![1, 3]);
+    }
+
+    #[test]
+    fn oplog_yields_heartbeats_matching_filters() {
+        let query = Query {
+            filter: Some(doc! { "op" => { "$in" => ["i", "n"] } }),
+            heartbeats: true,
+            ..Query::default()
+        };
+        let documents = vec![insert(1, "foo.bar"), noop(2), insert(3, "foo.bar")];
+        let oplog = Oplog::with_query(documents.into_iter(), query, None, false);
+
+        assert_eq!(seconds(oplog.collect()), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn check_client_side_accepts_queries_without_filters() {
+        let query = Query { kinds: Kinds::new(&[OperationKind::Insert]), ..Query::default() };
+
+        assert!(check_client_side(&query).is_ok());
+    }
+
+    #[test]
+    fn check_client_side_rejects_filters() {
+        let query = Query { filter: Some(doc! { "op" => "i" }), ..Query::default() };
+
+        match check_client_side(&query) {
+            Err(Error::UnsupportedFilter) => {}
+            _ => panic!("Expected unsupported filter error."),
+        }
+    }
+
+    #[test]
+    fn seconds_since_epoch_clamps_times_outside_timestamps() {
+        assert_eq!(seconds_since_epoch(UTC.ymd(2016, 11, 19).and_hms(13, 16, 34)), 1479561394);
+        assert_eq!(seconds_since_epoch(UTC.ymd(1969, 12, 31).and_hms(23, 59, 59)), 0);
+        assert_eq!(seconds_since_epoch(UTC.ymd(2200, 1, 1).and_hms(0, 0, 0)), u32::MAX);
+    }
+
+    #[test]
+    fn oplog_yields_heartbeats_filters_cannot_be_evaluated_against() {
+        let pattern = Bson::RegExp("^periodic".into(), "".into());
+        let query = Query {
+            filter: Some(doc! { "o.msg" => { "$in" => [(pattern)] } }),
+            heartbeats: true,
+            ..Query::default()
+        };
+        let documents = vec![noop(1), insert(2, "foo.bar")];
+        let oplog = Oplog::with_query(documents.into_iter(), query, None, false);
+
+        assert_eq!(seconds(oplog.collect()), vec![1, 2]);
+    }
+
+    #[test]
+    fn oplog_yields_errors_from_a_source() {
+        let mut oplog = Oplog::from_source(FlakySource {
+            documents: vec![insert(1, "foo.bar"), insert(2, "foo.bar")].into_iter(),
+            failed: false,
+            resumed_after: None,
+        });
+        let results: Vec<_> = oplog.try_iter().collect();
+
+        assert_eq!(results.len(), 3);
+        assert!(results[1].is_err());
+    }
+
+    #[test]
+    fn oplog_resumes_a_source_after_the_last_position_read() {
+        let source = FlakySource {
+            documents: vec![insert(1, "foo.bar"), insert(2, "foo.bar")].into_iter(),
+            failed: false,
+            resumed_after: None,
+        };
+        let backoff = Backoff::new(Duration::from_millis(0), Duration::from_millis(0));
+        let mut oplog = Oplog::with_query(source, Query::default(), Some(backoff), false);
+
+        assert_eq!(seconds(oplog.by_ref().collect()), vec![1, 2]);
+        assert_eq!(oplog.source.resumed_after, Some(OpTime::new(1, 0, None)));
+    }
+
+    #[test]
+    fn oplog_ends_when_a_closed_source_cannot_reconnect() {
+        let mut oplog = Oplog::from_source(ClosingSource::new(vec![vec![insert(1, "foo.bar")]]));
+        let results: Vec<_> = oplog.try_iter().collect();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap().optime().seconds, 1);
+        match results[1] {
+            Err(Error::CursorClosed) => {}
+            _ => panic!("Expected cursor closed error."),
+        }
+    }
+
+    #[test]
+    fn oplog_resumes_a_closed_source() {
+        let source = ClosingSource::new(vec![vec![insert(1, "foo.bar")],
+                                             vec![insert(2, "foo.bar")]]);
+        let backoff = Backoff::new(Duration::from_millis(0), Duration::from_millis(0));
+        let mut oplog = Oplog::with_query(source, Query::default(), Some(backoff), false);
+        let results: Vec<_> = oplog.try_iter().collect();
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[1].as_ref().unwrap().optime().seconds, 2);
+        assert_eq!(oplog.source.resumed_after,
+                   vec![Some(OpTime::new(1, 0, None)), Some(OpTime::new(2, 0, None))]);
+    }
+
+    #[test]
+    fn oplog_does_not_retry_resuming_after_a_rollback() {
+        let source = RejectingSource {
+            documents: vec![insert(1, "foo.bar")].into_iter(),
+            closing: true,
+            error: Some(Error::Rollback {
+                common: None,
+                last: OpTime::new(1, 0, None),
+            }),
+            resumes: 0,
+        };
+        let backoff = Backoff {
+            max_attempts: Some(3),
+            ..Backoff::new(Duration::from_millis(0), Duration::from_millis(0))
+        };
+        let mut oplog = Oplog::with_query(source, Query::default(), Some(backoff), false);
+        let results: Vec<_> = oplog.try_iter().collect();
+
+        assert_eq!(results.len(), 2);
+        match results[1] {
+            Err(Error::Rollback { .. }) => {}
+            _ => panic!("Expected rollback error."),
+        }
+        assert_eq!(oplog.source.resumes, 1);
+    }
+
+    #[test]
+    fn oplog_ends_after_a_rollback_when_reading_fails() {
+        let source = RejectingSource {
+            documents: vec![insert(1, "foo.bar")].into_iter(),
+            closing: false,
+            error: Some(Error::Rollback {
+                common: None,
+                last: OpTime::new(1, 0, None),
+            }),
+            resumes: 0,
+        };
+        let backoff = Backoff {
+            max_attempts: Some(3),
+            ..Backoff::new(Duration::from_millis(0), Duration::from_millis(0))
+        };
+        let mut oplog = Oplog::with_query(source, Query::default(), Some(backoff), false);
+        let results: Vec<_> = oplog.try_iter().take(3).collect();
+
+        assert_eq!(results.len(), 2);
+        match results[1] {
+            Err(Error::Rollback { .. }) => {}
+            _ => panic!("Expected rollback error."),
+        }
+        assert_eq!(oplog.source.resumes, 1);
+    }
+
+    #[test]
+    fn oplog_does_not_retry_resuming_after_the_oplog_rolls_over() {
+        let source = RejectingSource {
+            documents: vec![insert(1, "foo.bar")].into_iter(),
+            closing: true,
+            error: Some(Error::OplogRolledOver {
+                requested: OpTime::new(1, 0, None),
+                oldest: OpTime::new(2, 0, None),
+            }),
+            resumes: 0,
+        };
+        let backoff = Backoff {
+            max_attempts: Some(3),
+            ..Backoff::new(Duration::from_millis(0), Duration::from_millis(0))
+        };
+        let mut oplog = Oplog::with_query(source, Query::default(), Some(backoff), false);
+        let results: Vec<_> = oplog.try_iter().collect();
+
+        assert_eq!(results.len(), 2);
+        match results[1] {
+            Err(Error::OplogRolledOver { .. }) => {}
+            _ => panic!("Expected rollover error."),
+        }
+        assert_eq!(oplog.source.resumes, 1);
+    }
+
+    #[test]
+    fn oplog_does_not_yield_transaction_entries_outside_namespaces() {
+        let query = Query {
+            namespaces: Namespaces::new(&["foo.bar"], &[]).unwrap(),
+            ..Query::default()
+        };
+        let documents = vec![
+            transaction(1, 0, doc! {
+                "applyOps" => [{ "op" => "i", "ns" => "foo.baz", "o" => { "_id" => 1 } }],
+                "prepare" => true
+            }),
+            transaction(2, 1, doc! {
+                "commitTransaction" => 1,
+                "commitTimestamp" => (Bson::TimeStamp(1 << 32))
+            }),
+            insert(3, "foo.bar"),
+        ];
+        let oplog = Oplog::with_query(documents.into_iter(), query, None, false);
+
+        assert_eq!(seconds(oplog.collect()), vec![3]);
+    }
+
+    #[test]
+    fn oplog_keeps_transaction_entries_outside_namespaces_for_transactions() {
+        let query = Query {
+            namespaces: Namespaces::new(&["foo.bar"], &[]).unwrap(),
+            ..Query::default()
+        };
+        let documents = vec![
+            transaction(1, 0, doc! {
+                "applyOps" => [{ "op" => "i", "ns" => "foo.baz", "o" => { "_id" => 1 } }],
+                "partialTxn" => true
+            }),
+            transaction(2, 1, doc! {
+                "applyOps" => [{ "op" => "i", "ns" => "foo.bar", "o" => { "_id" => 2 } }],
+                "count" => 2i64
+            }),
+        ];
+        let oplog = Oplog::with_query(documents.into_iter(), query, None, false);
+        let transactions: Vec<_> = oplog.transactions().collect();
+
+        assert_eq!(transactions.len(), 1);
+        match transactions[0] {
+            Operation::Transaction { ref operations, .. } => {
+                assert_eq!(seconds(operations.clone()), vec![2]);
+            }
+            _ => panic!("Expected transaction."),
+        }
+    }
+
+    #[test]
+    fn oplog_does_not_yield_transaction_entries_of_other_kinds() {
+        let query = Query { kinds: Kinds::new(&[OperationKind::Insert]), ..Query::default() };
+        let documents = vec![
+            transaction(1, 0, doc! {
+                "applyOps" => [{ "op" => "d", "ns" => "foo.bar", "o" => { "_id" => 1 } }],
+                "prepare" => true
+            }),
+            transaction(2, 1, doc! {
+                "commitTransaction" => 1,
+                "commitTimestamp" => (Bson::TimeStamp(1 << 32))
+            }),
+            insert(3, "foo.bar"),
+        ];
+        let oplog = Oplog::with_query(documents.clone().into_iter(), query.clone(), None, false);
+
+        assert_eq!(seconds(oplog.collect()), vec![3]);
+
+        let oplog = Oplog::with_query(documents.into_iter(), query, None, false);
+
+        assert_eq!(seconds(oplog.transactions().collect()), vec![3]);
+    }
 }
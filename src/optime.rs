@@ -0,0 +1,237 @@
+//! The optime module is responsible for representing a position in a MongoDB oplog without losing
+//! any precision.
+//!
+//! MongoDB records the time of an operation as a BSON timestamp: a 64-bit value whose high 32 bits
+//! are seconds since the Unix epoch and whose low 32 bits are an ordinal incremented for every
+//! operation in the same second. Since protocol version 1, replica sets also record the election
+//! term alongside it.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use bson::{Bson, Document};
+use chrono::{DateTime, UTC, TimeZone};
+use Result;
+
+/// The exact position of an operation in the oplog.
+///
+/// `OpTime`s are totally ordered by their timestamp (seconds, then increment) and then by their
+/// term, with a missing term before any other, so they can be compared to determine which of two
+/// operations happened first. Use `OpTime::comparable_to` when comparing against a position that
+/// may not have a term, e.g. one given by a user.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct OpTime {
+    /// The number of seconds since the Unix epoch.
+    pub seconds: u32,
+    /// The ordinal of the operation within the second.
+    pub increment: u32,
+    /// The replica set election term of the operation, if recorded.
+    pub term: Option<i64>,
+}
+
+impl OpTime {
+    /// Returns a new `OpTime` for the given seconds, increment and optional term.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use oplog::OpTime;
+    ///
+    /// let optime = OpTime::new(1479561394, 1, Some(2));
+    /// ```
+    pub fn new(seconds: u32, increment: u32, term: Option<i64>) -> OpTime {
+        OpTime {
+            seconds: seconds,
+            increment: increment,
+            term: term,
+        }
+    }
+
+    /// Returns a new `OpTime` from a raw BSON timestamp and optional term.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use oplog::OpTime;
+    ///
+    /// let optime = OpTime::from_timestamp(1479561394 << 32 | 1, None);
+    ///
+    /// assert_eq!(optime, OpTime::new(1479561394, 1, None));
+    /// ```
+    pub fn from_timestamp(timestamp: i64, term: Option<i64>) -> OpTime {
+        OpTime::new((timestamp >> 32) as u32, timestamp as u32, term)
+    }
+
+    /// Returns the raw BSON timestamp of this position, discarding the term.
+    pub fn timestamp(&self) -> i64 {
+        (self.seconds as i64) << 32 | self.increment as i64
+    }
+
+    /// Returns this position without its term if the given position has none so that the two are
+    /// compared by timestamp alone.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use oplog::OpTime;
+    ///
+    /// let since = OpTime::new(1479561394, 1, None);
+    /// let optime = OpTime::new(1479561394, 1, Some(2));
+    ///
+    /// assert!(optime > since);
+    /// assert_eq!(optime.comparable_to(since), since);
+    /// ```
+    pub fn comparable_to(self, other: OpTime) -> OpTime {
+        match other.term {
+            Some(_) => self,
+            None => OpTime { term: None, ..self },
+        }
+    }
+
+    /// Returns the time of this position as a UTC `DateTime`.
+    ///
+    /// Note that this is only precise to the second as the increment is an ordinal rather than a
+    /// fraction of a second.
+    pub fn datetime(&self) -> DateTime<UTC> {
+        UTC.timestamp(self.seconds as i64, 0)
+    }
+
+    /// Returns the position of an oplog entry from its `ts` and optional `t` fields.
+    pub(crate) fn from_document(document: &Document) -> Result<OpTime> {
+        let ts = document.get_time_stamp("ts")?;
+
+        Ok(OpTime::from_timestamp(ts, term(document)))
+    }
+}
+
+impl Ord for OpTime {
+    fn cmp(&self, other: &OpTime) -> Ordering {
+        self.timestamp().cmp(&other.timestamp()).then(self.term.cmp(&other.term))
+    }
+}
+
+impl PartialOrd for OpTime {
+    fn partial_cmp(&self, other: &OpTime) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl From<OpTime> for Bson {
+    fn from(optime: OpTime) -> Bson {
+        Bson::TimeStamp(optime.timestamp())
+    }
+}
+
+impl fmt::Display for OpTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Timestamp({}, {})", self.seconds, self.increment)?;
+
+        match self.term {
+            Some(term) => write!(f, " (term {})", term),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns the term of an oplog entry, if any.
+///
+/// The server writes this as a 64-bit integer but we also accept 32-bit integers as they are what
+/// hand-written `applyOps` entries typically contain.
+fn term(document: &Document) -> Option<i64> {
+    match document.get("t") {
+        Some(&Bson::I64(t)) => Some(t),
+        Some(&Bson::I32(t)) => Some(t as i64),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::Bson;
+    use chrono::{UTC, TimeZone};
+    use super::OpTime;
+
+    #[test]
+    fn optime_orders_by_increment_within_a_second() {
+        let first = OpTime::new(1479561394, 1, None);
+        let second = OpTime::new(1479561394, 2, None);
+
+        assert!(first < second);
+    }
+
+    #[test]
+    fn optime_orders_by_seconds_before_increment() {
+        let first = OpTime::new(1479561394, 9, Some(1));
+        let second = OpTime::new(1479561395, 1, Some(1));
+
+        assert!(first < second);
+    }
+
+    #[test]
+    fn optime_orders_by_timestamp_before_term() {
+        let first = OpTime::new(1479561394, 1, Some(2));
+        let second = OpTime::new(1479561394, 2, Some(1));
+
+        assert!(first < second);
+        assert!(OpTime::new(1479561394, 1, None) < OpTime::new(1479561394, 1, Some(1)));
+        assert!(OpTime::new(1479561394, 1, Some(1)) < OpTime::new(1479561394, 1, Some(2)));
+    }
+
+    #[test]
+    fn optime_compares_to_positions_without_terms_by_timestamp() {
+        let bound = OpTime::new(1479561394, 1, None);
+
+        assert_eq!(OpTime::new(1479561394, 1, Some(2)).comparable_to(bound), bound);
+        assert_eq!(OpTime::new(1479561394, 1, Some(2)).comparable_to(OpTime::new(1, 0, Some(1))),
+                   OpTime::new(1479561394, 1, Some(2)));
+    }
+
+    #[test]
+    fn optime_converts_to_bson_timestamps() {
+        let optime = OpTime::new(1479561394, 3, Some(2));
+
+        assert_eq!(Bson::from(optime), Bson::TimeStamp(1479561394 << 32 | 3));
+    }
+
+    #[test]
+    fn optime_round_trips_raw_timestamps() {
+        let optime = OpTime::from_timestamp(1479561394 << 32 | 3, Some(2));
+
+        assert_eq!(optime, OpTime::new(1479561394, 3, Some(2)));
+        assert_eq!(optime.timestamp(), 1479561394 << 32 | 3);
+    }
+
+    #[test]
+    fn optime_returns_datetimes() {
+        let optime = OpTime::new(1479561394, 3, None);
+
+        assert_eq!(optime.datetime(), UTC.timestamp(1479561394, 0));
+    }
+
+    #[test]
+    fn optime_reads_documents_with_terms() {
+        let doc = doc! {
+            "ts" => (Bson::TimeStamp(1479561394 << 32 | 3)),
+            "t" => 2i64
+        };
+
+        assert_eq!(OpTime::from_document(&doc).unwrap(),
+                   OpTime::new(1479561394, 3, Some(2)));
+    }
+
+    #[test]
+    fn optime_reads_documents_without_terms() {
+        let doc = doc! { "ts" => (Bson::TimeStamp(1479561394 << 32 | 3)) };
+
+        assert_eq!(OpTime::from_document(&doc).unwrap(),
+                   OpTime::new(1479561394, 3, None));
+    }
+
+    #[test]
+    fn optime_displays_timestamp_and_term() {
+        assert_eq!(OpTime::new(1479561394, 3, Some(2)).to_string(),
+                   "Timestamp(1479561394, 3) (term 2)");
+        assert_eq!(OpTime::new(1479561394, 3, None).to_string(),
+                   "Timestamp(1479561394, 3)");
+    }
+}
@@ -0,0 +1,556 @@
+//! The query module is responsible for composing the query sent to the server when reading the
+//! oplog from a user's filter and any start or end positions.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use bson::{Bson, Document};
+use mongodb::coll::options::FindOptions;
+use mongodb::common::{ReadMode, ReadPreference};
+use mongodb::db::ThreadedDatabase;
+use mongodb::{Client, ThreadedClient};
+
+use {Error, OpTime, Result};
+use cursor::{Cursor, COLLECTION};
+use kind::Kinds;
+use namespace::Namespaces;
+
+/// The position in the oplog from which to start.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Start {
+    /// Start from the given position inclusively.
+    Since(OpTime),
+    /// Start immediately after the given position.
+    After(OpTime),
+}
+
+impl Start {
+    /// Returns the position from which to start.
+    fn optime(&self) -> OpTime {
+        match *self {
+            Start::Since(optime) | Start::After(optime) => optime,
+        }
+    }
+}
+
+/// The server-side criteria for reading the oplog.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Query {
+    /// The user's filter.
+    pub filter: Option<Document>,
+    /// The namespaces to include and exclude.
+    pub namespaces: Namespaces,
+    /// The kinds of operation to include.
+    pub kinds: Kinds,
+    /// The position from which to start, if any.
+    pub start: Option<Start>,
+    /// The position after which to stop, if any.
+    pub until: Option<OpTime>,
+    /// Whether to stop at the end of the oplog rather than tailing it.
+    pub finite: bool,
+    /// Whether to read no-ops regardless of any other criteria so that the position of an idle
+    /// oplog still advances.
+    pub heartbeats: bool,
+    /// Whether to only read operations once they are majority-committed.
+    pub majority: bool,
+    /// The number of documents to return in each batch, if not the server's default.
+    pub batch_size: Option<i32>,
+    /// How long the server should await new entries for each batch while tailing, if not its
+    /// default of one second.
+    pub max_await_time: Option<Duration>,
+    /// Whether the server should keep the cursor open indefinitely while it is inactive.
+    pub no_cursor_timeout: bool,
+    /// The fields of each entry to return, if not all of them.
+    pub projection: Option<Document>,
+    /// Which members of the replica set to read from, if not the client's default.
+    pub read_mode: Option<ReadMode>,
+    /// The tags of the members to read from, in order of preference, when reading with a
+    /// `read_mode`.
+    pub tag_sets: Vec<BTreeMap<String, String>>,
+}
+
+impl Query {
+    /// Returns a cursor over the oplog, resuming after the given position if any.
+    ///
+    /// The cursor is tailable unless the query is finite.
+    ///
+    /// Returns an error if the position from which to start is no longer in the oplog or, when
+    /// resuming, if the last operation read has since been rolled back. The hash (`h`) of the last
+    /// operation read, if known, is compared too as entries have no term before protocol version 1.
+    pub fn find(&self,
+                client: &Client,
+                resume_after: Option<OpTime>,
+                last_hash: Option<i64>)
+                -> Result<Cursor> {
+        let coll = client.db("local").collection(COLLECTION);
+
+        if let Some(start) = self.start(resume_after) {
+            let mut opts = self.options();
+            opts.sort = Some(doc! { "$natural" => 1 });
+
+            if let Some(document) = coll.find_one(None, Some(opts))? {
+                check_rollover(start.optime(), OpTime::from_document(&document)?)?;
+            }
+        }
+
+        if let Some(last) = resume_after {
+            // Seek to the last position read rather than scanning the entire oplog for it.
+            let mut opts = self.options();
+            opts.oplog_replay = true;
+            opts.sort = Some(doc! { "$natural" => 1 });
+            let next = match coll.find_one(Some(doc! { "ts" => { "$gte" => last } }), Some(opts))? {
+                Some(document) => Some((OpTime::from_document(&document)?, hash(&document))),
+                None => None,
+            };
+
+            if is_rolled_back(last, last_hash, next) {
+                let mut opts = self.options();
+                opts.sort = Some(doc! { "$natural" => -1 });
+                let common = match coll.find_one(Some(common_filter(last)), Some(opts))? {
+                    Some(document) => Some(OpTime::from_document(&document)?),
+                    None => None,
+                };
+
+                return Err(Error::Rollback {
+                    common: common,
+                    last: last,
+                });
+            }
+        }
+
+        // The server rejects a maximum await time for cursors that are not tailable.
+        let max_await_time = if self.finite { None } else { self.max_await_time };
+
+        Cursor::new(client,
+                    self.command(resume_after),
+                    self.batch_size,
+                    max_await_time,
+                    self.read_preference())
+    }
+
+    /// Returns the `find` command reading the oplog, resuming after the given position if any.
+    fn command(&self, resume_after: Option<OpTime>) -> Document {
+        let mut command = doc! { "find" => COLLECTION };
+
+        if let Some(filter) = self.filter(resume_after) {
+            command.insert("filter", filter);
+        }
+
+        if let Some(ref projection) = self.projection {
+            command.insert("projection", projection.to_owned());
+        }
+
+        if let Some(batch_size) = self.batch_size {
+            command.insert("batchSize", batch_size);
+        }
+
+        if !self.finite {
+            command.insert("tailable", true);
+            command.insert("awaitData", true);
+        }
+
+        command.insert("oplogReplay", self.start(resume_after).is_some());
+
+        if self.no_cursor_timeout {
+            command.insert("noCursorTimeout", true);
+        }
+
+        command
+    }
+
+    /// Returns the options shared by every query of the oplog.
+    fn options(&self) -> FindOptions {
+        let mut opts = FindOptions::new();
+        opts.read_preference = self.read_preference();
+
+        opts
+    }
+
+    /// Returns the read preference selecting which members of the replica set to read from, if not
+    /// the client's default.
+    fn read_preference(&self) -> Option<ReadPreference> {
+        self.read_mode.map(|mode| ReadPreference::new(mode, Some(self.tag_sets.to_owned())))
+    }
+
+    /// Returns the filter to send to the server, resuming after the given position if any.
+    pub fn filter(&self, resume_after: Option<OpTime>) -> Option<Document> {
+        let mut clauses = Vec::new();
+
+        if let Some(ref filter) = self.filter {
+            clauses.push(filter.to_owned());
+        }
+
+        clauses.extend(self.namespaces.clauses());
+        clauses.extend(self.kinds.clauses());
+
+        let mut clauses = match and(clauses) {
+            Some(filter) if self.heartbeats => {
+                vec![doc! { "$or" => [(Bson::Document(filter)), { "op" => "n" }] }]
+            }
+            Some(filter) => vec![filter],
+            None => Vec::new(),
+        };
+
+        match self.start(resume_after) {
+            Some(Start::Since(optime)) => clauses.push(doc! { "ts" => { "$gte" => optime } }),
+            Some(Start::After(optime)) => clauses.push(doc! { "ts" => { "$gt" => optime } }),
+            None => {}
+        }
+
+        // A tailable cursor only ends once an operation after `until` is read so we can only bound
+        // the query on the server when it is finite.
+        if let (true, Some(until)) = (self.finite, self.until) {
+            clauses.push(doc! { "ts" => { "$lte" => until } });
+        }
+
+        and(clauses)
+    }
+
+    /// Returns the position from which to read, preferring any position to resume after.
+    ///
+    /// Any start position lets the server use the `oplogReplay` optimisation to seek to it rather
+    /// than scanning the entire oplog.
+    fn start(&self, resume_after: Option<OpTime>) -> Option<Start> {
+        resume_after.map(Start::After).or(self.start)
+    }
+}
+
+/// Returns an error if the oldest entry in the oplog is after the requested position as any
+/// operations between them have been truncated from the oplog.
+fn check_rollover(requested: OpTime, oldest: OpTime) -> Result<()> {
+    if oldest.comparable_to(requested) > requested {
+        Err(Error::OplogRolledOver {
+            requested: requested,
+            oldest: oldest,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns whether the entry at the given position with the given hash, if known, has been rolled
+/// back given the position and hash of the first entry at or after it, if any.
+///
+/// An entry rolled back after an election may be replaced by one at the same timestamp so their
+/// terms are compared too if known and, failing that, their hashes.
+fn is_rolled_back(last: OpTime,
+                  last_hash: Option<i64>,
+                  next: Option<(OpTime, Option<i64>)>)
+                  -> bool {
+    let (next, next_hash) = match next {
+        Some(next) => next,
+        None => return true,
+    };
+
+    if next.comparable_to(last) != last.comparable_to(next) {
+        return true;
+    }
+
+    match (last.term, next.term, last_hash, next_hash) {
+        (Some(_), Some(_), _, _) => false,
+        (_, _, Some(last_hash), Some(next_hash)) => last_hash != next_hash,
+        _ => false,
+    }
+}
+
+/// Returns the hash (`h`) of the given oplog entry, if any.
+///
+/// Servers stopped writing hashes in MongoDB 4.2 once every entry had a term.
+pub(crate) fn hash(document: &Document) -> Option<i64> {
+    document.get_i64("h").ok()
+}
+
+/// Returns the filter matching entries before the given position that survive any rollback of it.
+///
+/// Entries written by a new primary after an election may be interleaved with those rolled back
+/// so only entries from the same or an earlier term are considered.
+fn common_filter(optime: OpTime) -> Document {
+    let mut filter = doc! { "ts" => { "$lt" => optime } };
+
+    if let Some(term) = optime.term {
+        filter.insert("t", doc! { "$lte" => term });
+    }
+
+    filter
+}
+
+/// Returns the conjunction of the given clauses, if any.
+fn and(mut clauses: Vec<Document>) -> Option<Document> {
+    match clauses.len() {
+        0 => None,
+        1 => clauses.pop(),
+        _ => Some(doc! { "$and" => (clauses.into_iter().map(Into::into).collect::<Vec<_>>()) }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use mongodb::common::ReadMode;
+    use {Error, OpTime};
+    use namespace::Namespaces;
+    use super::{check_rollover, common_filter, hash, is_rolled_back, Query, Start};
+
+    #[test]
+    fn query_is_empty_by_default() {
+        assert_eq!(Query::default().filter(None), None);
+    }
+
+    #[test]
+    fn query_preserves_user_filters() {
+        let query = Query { filter: Some(doc! { "op" => "i" }), ..Query::default() };
+
+        assert_eq!(query.filter(None), Some(doc! { "op" => "i" }));
+    }
+
+    #[test]
+    fn query_starts_since_positions() {
+        let query = Query {
+            start: Some(Start::Since(OpTime::new(1479561394, 1, None))),
+            ..Query::default()
+        };
+
+        assert_eq!(query.filter(None),
+                   Some(doc! { "ts" => { "$gte" => (OpTime::new(1479561394, 1, None)) } }));
+    }
+
+    #[test]
+    fn query_composes_start_positions_with_user_filters() {
+        let query = Query {
+            filter: Some(doc! { "op" => "i" }),
+            start: Some(Start::After(OpTime::new(1479561394, 1, None))),
+            ..Query::default()
+        };
+
+        assert_eq!(query.filter(None),
+                   Some(doc! {
+                       "$and" => [
+                           { "op" => "i" },
+                           { "ts" => { "$gt" => (OpTime::new(1479561394, 1, None)) } }
+                       ]
+                   }));
+    }
+
+    #[test]
+    fn query_composes_namespaces_with_user_filters() {
+        let query = Query {
+            filter: Some(doc! { "op" => "i" }),
+            namespaces: Namespaces::new(&[], &["app.sessions"]).unwrap(),
+            ..Query::default()
+        };
+
+        assert_eq!(query.filter(None),
+                   Some(doc! {
+                       "$and" => [
+                           { "op" => "i" },
+                           {
+                               "$or" => [
+                                   { "ns" => { "$nin" => ["app.sessions"] } },
+                                   { "op" => "c" }
+                               ]
+                           }
+                       ]
+                   }));
+    }
+
+    #[test]
+    fn query_reads_heartbeats_regardless_of_filters() {
+        let query = Query {
+            filter: Some(doc! { "op" => "i" }),
+            start: Some(Start::Since(OpTime::new(1479561394, 1, None))),
+            heartbeats: true,
+            ..Query::default()
+        };
+
+        assert_eq!(query.filter(None),
+                   Some(doc! {
+                       "$and" => [
+                           { "$or" => [{ "op" => "i" }, { "op" => "n" }] },
+                           { "ts" => { "$gte" => (OpTime::new(1479561394, 1, None)) } }
+                       ]
+                   }));
+    }
+
+    #[test]
+    fn query_bounds_finite_queries() {
+        let query = Query {
+            until: Some(OpTime::new(1479561394, 1, None)),
+            finite: true,
+            ..Query::default()
+        };
+
+        assert_eq!(query.filter(None),
+                   Some(doc! { "ts" => { "$lte" => (OpTime::new(1479561394, 1, None)) } }));
+    }
+
+    #[test]
+    fn query_does_not_bound_tailing_queries() {
+        let query = Query { until: Some(OpTime::new(1479561394, 1, None)), ..Query::default() };
+
+        assert_eq!(query.filter(None), None);
+    }
+
+    #[test]
+    fn query_commands_tail_the_oplog() {
+        let query = Query {
+            start: Some(Start::Since(OpTime::new(1479561394, 1, None))),
+            ..Query::default()
+        };
+
+        assert_eq!(query.command(None),
+                   doc! {
+                       "find" => "oplog.rs",
+                       "filter" => { "ts" => { "$gte" => (OpTime::new(1479561394, 1, None)) } },
+                       "tailable" => true,
+                       "awaitData" => true,
+                       "oplogReplay" => true
+                   });
+    }
+
+    #[test]
+    fn query_commands_do_not_tail_finite_queries() {
+        let query = Query { finite: true, ..Query::default() };
+
+        assert_eq!(query.command(None),
+                   doc! {
+                       "find" => "oplog.rs",
+                       "oplogReplay" => false
+                   });
+    }
+
+    #[test]
+    fn query_commands_tune_the_cursor() {
+        let query = Query {
+            batch_size: Some(1000),
+            no_cursor_timeout: true,
+            projection: Some(doc! { "o.payload" => 0 }),
+            ..Query::default()
+        };
+
+        assert_eq!(query.command(None),
+                   doc! {
+                       "find" => "oplog.rs",
+                       "projection" => { "o.payload" => 0 },
+                       "batchSize" => 1000,
+                       "tailable" => true,
+                       "awaitData" => true,
+                       "oplogReplay" => false,
+                       "noCursorTimeout" => true
+                   });
+    }
+
+    #[test]
+    fn query_options_read_from_the_default_members() {
+        assert!(Query::default().options().read_preference.is_none());
+    }
+
+    #[test]
+    fn query_options_read_from_the_given_members() {
+        let query = Query { read_mode: Some(ReadMode::Secondary), ..Query::default() };
+        let read_preference = query.options().read_preference.expect("Expected read preference.");
+
+        assert_eq!(read_preference.mode, ReadMode::Secondary);
+        assert!(read_preference.tag_sets.is_empty());
+    }
+
+    #[test]
+    fn query_options_read_from_the_given_tagged_members() {
+        let mut tags = BTreeMap::new();
+        tags.insert("dc".to_owned(), "east".to_owned());
+        let query = Query {
+            read_mode: Some(ReadMode::Nearest),
+            tag_sets: vec![tags.clone()],
+            ..Query::default()
+        };
+        let read_preference = query.options().read_preference.expect("Expected read preference.");
+
+        assert_eq!(read_preference.mode, ReadMode::Nearest);
+        assert_eq!(read_preference.tag_sets, vec![tags]);
+    }
+
+    #[test]
+    fn query_resumes_after_positions() {
+        let query = Query {
+            start: Some(Start::Since(OpTime::new(1479561394, 1, None))),
+            ..Query::default()
+        };
+
+        assert_eq!(query.filter(Some(OpTime::new(1479561395, 2, Some(1)))),
+                   Some(doc! { "ts" => { "$gt" => (OpTime::new(1479561395, 2, Some(1))) } }));
+    }
+
+    #[test]
+    fn check_rollover_accepts_positions_still_in_the_oplog() {
+        let oldest = OpTime::new(1479561394, 1, Some(1));
+
+        assert!(check_rollover(OpTime::new(1479561394, 1, None), oldest).is_ok());
+        assert!(check_rollover(OpTime::new(1479561395, 1, None), oldest).is_ok());
+    }
+
+    #[test]
+    fn check_rollover_rejects_truncated_positions() {
+        match check_rollover(OpTime::new(1479561394, 1, None), OpTime::new(1479561394, 2, None)) {
+            Err(Error::OplogRolledOver { requested, oldest }) => {
+                assert_eq!(requested, OpTime::new(1479561394, 1, None));
+                assert_eq!(oldest, OpTime::new(1479561394, 2, None));
+            }
+            _ => panic!("Expected rollover error."),
+        }
+    }
+
+    #[test]
+    fn is_rolled_back_accepts_positions_still_in_the_oplog() {
+        let last = OpTime::new(1479561394, 1, Some(2));
+
+        assert!(!is_rolled_back(last, None, Some((OpTime::new(1479561394, 1, Some(2)), None))));
+        assert!(!is_rolled_back(last, None, Some((OpTime::new(1479561394, 1, None), None))));
+        assert!(!is_rolled_back(OpTime::new(1479561394, 1, None), None, Some((last, None))));
+    }
+
+    #[test]
+    fn is_rolled_back_rejects_missing_positions() {
+        let last = OpTime::new(1479561394, 1, Some(2));
+
+        assert!(is_rolled_back(last, None, None));
+        assert!(is_rolled_back(last, None, Some((OpTime::new(1479561394, 2, Some(2)), None))));
+    }
+
+    #[test]
+    fn is_rolled_back_rejects_positions_replaced_in_later_terms() {
+        assert!(is_rolled_back(OpTime::new(1479561394, 1, Some(2)),
+                               None,
+                               Some((OpTime::new(1479561394, 1, Some(3)), None))));
+    }
+
+    #[test]
+    fn is_rolled_back_compares_hashes_without_terms() {
+        let last = OpTime::new(1479561394, 1, None);
+
+        assert!(!is_rolled_back(last, Some(42), Some((last, Some(42)))));
+        assert!(!is_rolled_back(last, None, Some((last, Some(42)))));
+        assert!(is_rolled_back(last, Some(42), Some((last, Some(43)))));
+    }
+
+    #[test]
+    fn is_rolled_back_ignores_hashes_with_terms() {
+        let last = OpTime::new(1479561394, 1, Some(2));
+
+        assert!(!is_rolled_back(last, Some(42), Some((last, Some(43)))));
+    }
+
+    #[test]
+    fn hash_reads_entry_hashes() {
+        assert_eq!(hash(&doc! { "h" => (-1742072865587022793i64) }), Some(-1742072865587022793));
+        assert_eq!(hash(&doc! { "op" => "n" }), None);
+    }
+
+    #[test]
+    fn common_filter_excludes_later_terms() {
+        assert_eq!(common_filter(OpTime::new(1479561394, 1, Some(2))),
+                   doc! {
+                       "ts" => { "$lt" => (OpTime::new(1479561394, 1, None)) },
+                       "t" => { "$lte" => 2i64 }
+                   });
+    }
+}
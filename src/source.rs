@@ -0,0 +1,232 @@
+//! The source module is responsible for providing the raw BSON documents of the oplog, whether
+//! read from a MongoDB server, from memory or from a file of BSON documents.
+//!
+//! This allows an `Oplog` to apply its filters, resumption and transaction logic to documents
+//! from anywhere, e.g. to test them without a live replica set.
+
+use std::io::{BufRead, BufReader, Read};
+use std::vec;
+
+use bson::{self, Document};
+use mongodb::Client;
+
+use {Error, OpTime, Result};
+use cursor::Cursor;
+use majority::{Majority, ReplicaSetStatus};
+use query::{self, Query};
+
+/// A source of raw oplog documents.
+pub trait OplogSource {
+    /// Returns the result of reading the next document or `None` if there is none available.
+    ///
+    /// Tailing sources may return `None` when no document is available yet, otherwise `None`
+    /// marks the end of the source.
+    fn next_document(&mut self) -> Option<Result<Document>>;
+
+    /// Returns whether this source awaits new documents rather than ending when there are none.
+    ///
+    /// This is `false` by default.
+    fn is_tailing(&self) -> bool {
+        false
+    }
+
+    /// Try to replace a lost source with one resuming after the given position, if any.
+    ///
+    /// Returns `None` by default for sources that cannot be resumed so that the error which lost
+    /// them is returned instead.
+    fn resume(&mut self, _after: Option<OpTime>) -> Option<Result<()>> {
+        None
+    }
+
+    /// Returns whether a tailing source has been closed and will never yield another document
+    /// unless resumed, e.g. as the server closed its cursor.
+    ///
+    /// This is `false` by default.
+    fn is_closed(&self) -> bool {
+        false
+    }
+}
+
+/// A source of documents read from the oplog of a MongoDB replica set.
+///
+/// This is the default source of an `Oplog` as built by `OplogBuilder`.
+pub struct CursorSource {
+    /// The MongoDB client used to query the oplog.
+    client: Client,
+    /// The query used to read the oplog.
+    query: Query,
+    /// The internal MongoDB cursor for the current position in the oplog.
+    cursor: Cursor,
+    /// The gate holding back documents until they are majority-committed, if requested.
+    majority: Option<Majority<ReplicaSetStatus>>,
+    /// The position and hash of the last document read, if any, to detect its rollback on
+    /// servers without terms.
+    last_hash: Option<(OpTime, i64)>,
+}
+
+impl CursorSource {
+    /// Returns a new source executing the given query with the given MongoDB client.
+    pub(crate) fn new(client: &Client, query: Query) -> Result<CursorSource> {
+        let cursor = query.find(client, None, None)?;
+        let majority = if query.majority {
+            Some(Majority::new(ReplicaSetStatus::new(client)))
+        } else {
+            None
+        };
+
+        Ok(CursorSource {
+            client: client.clone(),
+            query: query,
+            cursor: cursor,
+            majority: majority,
+            last_hash: None,
+        })
+    }
+}
+
+impl OplogSource for CursorSource {
+    fn next_document(&mut self) -> Option<Result<Document>> {
+        let tailing = self.is_tailing();
+        let result = match self.majority {
+            Some(ref mut majority) => majority.next(&mut self.cursor, tailing),
+            None => self.cursor.next(),
+        };
+
+        if let Some(Ok(ref document)) = result {
+            self.last_hash = match (OpTime::from_document(document), query::hash(document)) {
+                (Ok(optime), Some(hash)) => Some((optime, hash)),
+                _ => None,
+            };
+        }
+
+        result
+    }
+
+    fn is_tailing(&self) -> bool {
+        !self.query.finite
+    }
+
+    fn resume(&mut self, after: Option<OpTime>) -> Option<Result<()>> {
+        if let Some(ref mut majority) = self.majority {
+            majority.reset();
+        }
+
+        let last_hash = match self.last_hash {
+            Some((optime, hash)) if Some(optime) == after => Some(hash),
+            _ => None,
+        };
+
+        Some(self.query.find(&self.client, after, last_hash).map(|cursor| self.cursor = cursor))
+    }
+
+    fn is_closed(&self) -> bool {
+        let pending = matches!(self.majority, Some(ref majority) if majority.is_pending());
+
+        !pending && self.cursor.is_closed()
+    }
+}
+
+/// A source of documents held in memory, e.g. for testing.
+impl OplogSource for vec::IntoIter<Document> {
+    fn next_document(&mut self) -> Option<Result<Document>> {
+        self.next().map(Ok)
+    }
+}
+
+/// A source of documents read from consecutive BSON documents, e.g. a file written by
+/// `mongodump`.
+///
+/// Reading ends at the end of the input or after the first error.
+pub struct BsonSource<R> {
+    /// The buffered input.
+    reader: BufReader<R>,
+    /// Whether reading has ended due to an error.
+    failed: bool,
+}
+
+impl<R: Read> BsonSource<R> {
+    /// Returns a new source reading BSON documents from the given input.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::fs::File;
+    /// use oplog::{BsonSource, Oplog};
+    ///
+    /// let file = File::open("oplog.bson").expect("Failed to open oplog.bson.");
+    ///
+    /// for operation in Oplog::from_source(BsonSource::new(file)) {
+    ///     // Do something with operation...
+    /// }
+    /// ```
+    pub fn new(reader: R) -> BsonSource<R> {
+        BsonSource {
+            reader: BufReader::new(reader),
+            failed: false,
+        }
+    }
+}
+
+impl<R: Read> OplogSource for BsonSource<R> {
+    fn next_document(&mut self) -> Option<Result<Document>> {
+        if self.failed {
+            return None;
+        }
+
+        let result = match self.reader.fill_buf() {
+            Ok([]) => return None,
+            Ok(_) => bson::decode_document(&mut self.reader).map_err(Error::from),
+            Err(err) => Err(Error::from(err)),
+        };
+
+        self.failed = result.is_err();
+
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use bson::{self, Bson};
+    use Error;
+    use super::{BsonSource, OplogSource};
+
+    #[test]
+    fn bson_source_reads_consecutive_documents() {
+        let mut bytes = Vec::new();
+        bson::encode_document(&mut bytes, &doc! { "ts" => (Bson::TimeStamp(1 << 32)) }).unwrap();
+        bson::encode_document(&mut bytes, &doc! { "ts" => (Bson::TimeStamp(2 << 32)) }).unwrap();
+        let mut source = BsonSource::new(Cursor::new(bytes));
+
+        assert_eq!(source.next_document().unwrap().unwrap(),
+                   doc! { "ts" => (Bson::TimeStamp(1 << 32)) });
+        assert_eq!(source.next_document().unwrap().unwrap(),
+                   doc! { "ts" => (Bson::TimeStamp(2 << 32)) });
+        assert!(source.next_document().is_none());
+    }
+
+    #[test]
+    fn bson_source_ends_after_truncated_documents() {
+        let mut bytes = Vec::new();
+        bson::encode_document(&mut bytes, &doc! { "op" => "n" }).unwrap();
+        bytes.pop();
+        let mut source = BsonSource::new(Cursor::new(bytes));
+
+        match source.next_document() {
+            Some(Err(Error::Decode(_))) => {}
+            _ => panic!("Expected decode error."),
+        }
+        assert!(source.next_document().is_none());
+    }
+
+    #[test]
+    fn memory_source_yields_documents() {
+        let mut source = vec![doc! { "op" => "n" }].into_iter();
+
+        assert_eq!(source.next_document().unwrap().unwrap(), doc! { "op" => "n" });
+        assert!(source.next_document().is_none());
+        assert!(!source.is_tailing());
+    }
+}
@@ -0,0 +1,131 @@
+//! The stream module is responsible for exposing an `Oplog` as an asynchronous `Stream` of
+//! operations for use with futures-based runtimes such as tokio.
+//!
+//! As the MongoDB driver only offers blocking I/O, each stream is backed by its own OS thread
+//! reading the oplog, just like an `OplogHandle`, which hands each operation over a bounded channel
+//! so that a slow consumer applies backpressure. The stream itself never blocks the executor
+//! polling it but it does not save a thread per oplog.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::channel::mpsc;
+use futures::executor;
+use futures::{SinkExt, Stream};
+
+use {Operation, Oplog, OplogSource, Result};
+use handle::{self, OperationSender};
+
+/// An asynchronous `Stream` of the result of reading each operation from an `Oplog`.
+///
+/// The oplog is read on a dedicated OS thread for as long as the stream exists. Dropping the stream
+/// cancels it, stopping the thread once it next receives an operation or finds none available.
+///
+/// This is created by `Oplog::into_stream` or `OplogBuilder::build_stream` and requires the
+/// `async` feature.
+pub struct OplogStream {
+    receiver: mpsc::Receiver<Result<Operation>>,
+}
+
+impl OplogStream {
+    /// Returns a new stream reading the given oplog on a new thread, buffering up to the given
+    /// number of operations.
+    pub(crate) fn new<S>(oplog: Oplog<S>, capacity: usize) -> OplogStream
+        where S: OplogSource + Send + 'static
+    {
+        let (sender, receiver) = mpsc::channel(capacity);
+        handle::drive(oplog, sender);
+
+        OplogStream { receiver: receiver }
+    }
+}
+
+impl OperationSender for mpsc::Sender<Result<Operation>> {
+    fn is_closed(&self) -> bool {
+        mpsc::Sender::is_closed(self)
+    }
+
+    fn send(&mut self, result: Result<Operation>) -> bool {
+        executor::block_on(SinkExt::send(self, result)).is_ok()
+    }
+}
+
+impl Stream for OplogStream {
+    type Item = Result<Operation>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use bson::{Bson, Document};
+    use futures::executor;
+    use {Oplog, OplogSource, Result};
+
+    fn insert(seconds: i64) -> Document {
+        doc! {
+            "ts" => (Bson::TimeStamp(seconds << 32)),
+            "v" => 2,
+            "op" => "i",
+            "ns" => "foo.bar",
+            "o" => { "_id" => seconds }
+        }
+    }
+
+    /// A tailing source that never has any documents and records when it is dropped.
+    struct IdleSource {
+        dropped: Arc<AtomicBool>,
+    }
+
+    impl OplogSource for IdleSource {
+        fn next_document(&mut self) -> Option<Result<Document>> {
+            thread::sleep(Duration::from_millis(1));
+
+            None
+        }
+
+        fn is_tailing(&self) -> bool {
+            true
+        }
+    }
+
+    impl Drop for IdleSource {
+        fn drop(&mut self) {
+            self.dropped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn oplog_stream_yields_operations() {
+        let oplog = Oplog::from_source(vec![insert(1), insert(2)].into_iter());
+        let results: Vec<_> = executor::block_on_stream(oplog.into_stream(1)).collect();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].as_ref().unwrap().optime().seconds, 2);
+    }
+
+    #[test]
+    fn oplog_stream_stops_reading_when_dropped() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let stream = Oplog::from_source(IdleSource { dropped: dropped.clone() }).into_stream(1);
+
+        drop(stream);
+
+        for _ in 0..1000 {
+            if dropped.load(Ordering::SeqCst) {
+                return;
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        panic!("Expected the oplog to be dropped.");
+    }
+}
@@ -0,0 +1,278 @@
+//! The transaction module is responsible for reassembling multi-document transactions from the
+//! oplog entries MongoDB writes for them.
+//!
+//! From MongoDB 4.0, a committed transaction is written as a single `applyOps` command tagged
+//! with the session and transaction number that ran it. From MongoDB 4.2, large transactions are
+//! split over several `partialTxn` entries linked by their `prevOpTime` and prepared transactions
+//! are only committed or aborted by a later `commitTransaction` or `abortTransaction` command.
+
+use std::collections::HashMap;
+
+use {CommandKind, Operation, OperationMeta, OpTime};
+
+/// An iterator adaptor that yields each committed transaction as a single
+/// `Operation::Transaction`.
+///
+/// Entries belonging to transactions that have yet to commit are buffered until the transaction
+/// commits, and aborted transactions are dropped entirely, as are committed transactions whose
+/// operations were all filtered out. Every other operation is passed through as it is.
+///
+/// Note that transactions whose first entries were never seen (e.g. because they were written
+/// before iteration began or filtered out) cannot be reassembled and are also dropped.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate mongodb;
+/// # extern crate oplog;
+/// use mongodb::{Client, ThreadedClient};
+/// use oplog::Oplog;
+///
+/// # fn main() {
+/// let client = Client::connect("localhost", 27017).expect("Failed to connect to MongoDB.");
+///
+/// if let Ok(oplog) = Oplog::new(&client) {
+///     for operation in oplog.transactions() {
+///         // Do something with operation or committed transaction...
+///     }
+/// }
+/// # }
+/// ```
+pub struct Transactions<I> {
+    /// The underlying operations.
+    operations: I,
+    /// The operations of uncommitted transactions keyed by the position of their latest entry.
+    pending: HashMap<OpTime, Vec<Operation>>,
+}
+
+impl<I> Transactions<I>
+    where I: Iterator<Item = Operation>
+{
+    /// Returns a new adaptor reassembling transactions from the given operations.
+    pub fn new(operations: I) -> Transactions<I> {
+        Transactions {
+            operations: operations,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Apply the next operation, returning it or any transaction it commits.
+    fn apply(&mut self, operation: Operation) -> Option<Operation> {
+        match operation {
+            Operation::ApplyOps { meta, operations, partial, prepare, .. }
+                if meta.txn_number.is_some() => {
+                let mut buffered = self.take(meta.prev_optime)?;
+                buffered.extend(operations);
+
+                if partial || prepare {
+                    self.pending.insert(meta.optime, buffered);
+
+                    None
+                } else {
+                    transaction(meta, buffered)
+                }
+            }
+            Operation::Command { meta, command: CommandKind::CommitTransaction { .. }, .. } => {
+                let operations = self.take(meta.prev_optime)?;
+
+                transaction(meta, operations)
+            }
+            Operation::Command { meta, command: CommandKind::AbortTransaction, .. } => {
+                self.take(meta.prev_optime);
+
+                None
+            }
+            operation => Some(operation),
+        }
+    }
+
+    /// Take the operations buffered for the transaction entry at the given position.
+    ///
+    /// Returns an empty vector for the first entry of a transaction and `None` if the previous
+    /// entry was never seen.
+    fn take(&mut self, prev_optime: Option<OpTime>) -> Option<Vec<Operation>> {
+        match prev_optime {
+            Some(prev_optime) => self.pending.remove(&prev_optime),
+            None => Some(Vec::new()),
+        }
+    }
+}
+
+/// Returns a committed transaction of the given operations or `None` if they were all filtered out.
+fn transaction(meta: OperationMeta, operations: Vec<Operation>) -> Option<Operation> {
+    if operations.is_empty() {
+        return None;
+    }
+
+    Some(Operation::Transaction {
+        meta: meta,
+        operations: operations,
+    })
+}
+
+impl<I> Iterator for Transactions<I>
+    where I: Iterator<Item = Operation>
+{
+    type Item = Operation;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(operation) = self.operations.next() {
+            if let Some(operation) = self.apply(operation) {
+                return Some(operation);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{Bson, Document};
+    use bson::spec::BinarySubtype;
+    use Operation;
+    use super::Transactions;
+
+    fn entry(increment: i64, prev_increment: i64, o: Document) -> Operation {
+        let doc = doc! {
+            "lsid" => {
+                "id" => (Bson::Binary(BinarySubtype::Uuid, vec![1, 2, 3]))
+            },
+            "txnNumber" => 1i64,
+            "op" => "c",
+            "ns" => "admin.$cmd",
+            "o" => o,
+            "ts" => (Bson::TimeStamp(1483789052 << 32 | increment)),
+            "t" => 1i64,
+            "v" => 2i64,
+            "prevOpTime" => {
+                "ts" => (Bson::TimeStamp(if prev_increment == 0 {
+                    0
+                } else {
+                    1483789052 << 32 | prev_increment
+                })),
+                "t" => 1i64
+            }
+        };
+
+        Operation::new(&doc).unwrap()
+    }
+
+    fn insert(id: i32) -> Bson {
+        Bson::Document(doc! {
+            "op" => "i",
+            "ns" => "foo.bar",
+            "o" => {
+                "_id" => id
+            }
+        })
+    }
+
+    fn inserted_ids(operation: &Operation) -> Vec<i32> {
+        match *operation {
+            Operation::Transaction { ref operations, .. } => {
+                operations.iter()
+                          .map(|operation| match *operation {
+                              Operation::Insert { ref document, .. } => {
+                                  document.get_i32("_id").unwrap()
+                              }
+                              _ => panic!("Expected insert."),
+                          })
+                          .collect()
+            }
+            _ => panic!("Expected transaction."),
+        }
+    }
+
+    #[test]
+    fn transactions_yield_single_entry_transactions() {
+        let operations = vec![entry(1, 0, doc! { "applyOps" => [(insert(1)), (insert(2))] })];
+        let transactions = Transactions::new(operations.into_iter()).collect::<Vec<_>>();
+
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(inserted_ids(&transactions[0]), vec![1, 2]);
+    }
+
+    #[test]
+    fn transactions_reassemble_partial_transactions() {
+        let operations = vec![
+            entry(1, 0, doc! { "applyOps" => [(insert(1))], "partialTxn" => true }),
+            entry(2, 1, doc! { "applyOps" => [(insert(2))], "partialTxn" => true }),
+            entry(3, 2, doc! { "applyOps" => [(insert(3))], "count" => 3i64 }),
+        ];
+        let transactions = Transactions::new(operations.into_iter()).collect::<Vec<_>>();
+
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(inserted_ids(&transactions[0]), vec![1, 2, 3]);
+        assert_eq!(transactions[0].optime().increment, 3);
+    }
+
+    #[test]
+    fn transactions_commit_prepared_transactions() {
+        let operations = vec![
+            entry(1, 0, doc! { "applyOps" => [(insert(1))], "prepare" => true }),
+            entry(2, 1, doc! {
+                "commitTransaction" => 1,
+                "commitTimestamp" => (Bson::TimeStamp(1483789052 << 32 | 1))
+            }),
+        ];
+        let transactions = Transactions::new(operations.into_iter()).collect::<Vec<_>>();
+
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(inserted_ids(&transactions[0]), vec![1]);
+    }
+
+    #[test]
+    fn transactions_drop_aborted_transactions() {
+        let operations = vec![
+            entry(1, 0, doc! { "applyOps" => [(insert(1))], "prepare" => true }),
+            entry(2, 1, doc! { "abortTransaction" => 1 }),
+        ];
+        let transactions = Transactions::new(operations.into_iter()).collect::<Vec<_>>();
+
+        assert!(transactions.is_empty());
+    }
+
+    #[test]
+    fn transactions_drop_empty_transactions() {
+        let operations = vec![
+            entry(1, 0, doc! { "applyOps" => [], "prepare" => true }),
+            entry(2, 1, doc! {
+                "commitTransaction" => 1,
+                "commitTimestamp" => (Bson::TimeStamp(1483789052 << 32 | 1))
+            }),
+            entry(3, 0, doc! { "applyOps" => [] }),
+        ];
+        let transactions = Transactions::new(operations.into_iter()).collect::<Vec<_>>();
+
+        assert!(transactions.is_empty());
+    }
+
+    #[test]
+    fn transactions_drop_transactions_missing_earlier_entries() {
+        let operations = vec![
+            entry(2, 1, doc! { "applyOps" => [(insert(2))], "partialTxn" => true }),
+            entry(3, 2, doc! { "applyOps" => [(insert(3))], "count" => 3i64 }),
+        ];
+        let transactions = Transactions::new(operations.into_iter()).collect::<Vec<_>>();
+
+        assert!(transactions.is_empty());
+    }
+
+    #[test]
+    fn transactions_pass_through_other_operations() {
+        let doc = doc! {
+            "ts" => (Bson::TimeStamp(1479561394 << 32)),
+            "v" => 2,
+            "op" => "c",
+            "ns" => "foo.$cmd",
+            "o" => {
+                "applyOps" => [(insert(1))]
+            }
+        };
+        let operation = Operation::new(&doc).unwrap();
+        let operations = Transactions::new(vec![operation.clone()].into_iter()).collect::<Vec<_>>();
+
+        assert_eq!(operations, vec![operation]);
+    }
+}
@@ -0,0 +1,256 @@
+//! The update module is responsible for describing the changes made by an update operation in a
+//! single model regardless of the format the server recorded them in.
+//!
+//! Before MongoDB 5.0, updates are recorded with `$set` and `$unset` operators (`$v: 1`). From
+//! MongoDB 5.0, they are recorded as a delta (`$v: 2`) where `u`, `i` and `d` fields update,
+//! insert and delete fields respectively and `s`-prefixed fields describe changes to nested
+//! documents and arrays.
+
+use bson::{Bson, Document};
+use {Error, Result};
+
+/// A description of the fields changed by an update operation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UpdateDescription {
+    /// The new values of every field set by the update, keyed by their dotted path.
+    pub updated_fields: Document,
+    /// The dotted paths of every field removed by the update.
+    pub removed_fields: Vec<String>,
+    /// Every array truncated by the update.
+    pub truncated_arrays: Vec<TruncatedArray>,
+}
+
+/// An array truncated by an update operation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TruncatedArray {
+    /// The dotted path of the array.
+    pub field: String,
+    /// The length of the array after truncation.
+    pub new_size: u32,
+}
+
+impl UpdateDescription {
+    /// Try to create a new `UpdateDescription` from the BSON update of an update operation.
+    ///
+    /// Replacement updates (i.e. those with no update operators) replace the entire document so
+    /// cannot be described and return an `Error::InvalidOperation`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[macro_use]
+    /// # extern crate bson;
+    /// # extern crate oplog;
+    /// use oplog::UpdateDescription;
+    ///
+    /// # fn main() {
+    /// let update = doc! {
+    ///     "$v" => 2,
+    ///     "diff" => {
+    ///         "u" => { "foo" => "bar" },
+    ///         "sbaz" => { "d" => { "quux" => false } }
+    ///     }
+    /// };
+    /// let description = UpdateDescription::new(&update).unwrap();
+    ///
+    /// assert_eq!(description.updated_fields, doc! { "foo" => "bar" });
+    /// assert_eq!(description.removed_fields, vec!["baz.quux".to_owned()]);
+    /// # }
+    /// ```
+    pub fn new(update: &Document) -> Result<UpdateDescription> {
+        let mut description = UpdateDescription {
+            updated_fields: Document::new(),
+            removed_fields: Vec::new(),
+            truncated_arrays: Vec::new(),
+        };
+
+        match update.get("$v") {
+            Some(&Bson::I32(2)) | Some(&Bson::I64(2)) => {
+                description.apply_diff("", update.get_document("diff")?)?
+            }
+            _ => description.apply_operators(update)?,
+        }
+
+        Ok(description)
+    }
+
+    /// Apply a `$v: 1` update made up of `$set` and `$unset` operators.
+    fn apply_operators(&mut self, update: &Document) -> Result<()> {
+        for (operator, fields) in update.iter() {
+            match (operator.as_str(), fields) {
+                ("$v", _) => {}
+                ("$set", &Bson::Document(ref fields)) => {
+                    for (field, value) in fields.iter() {
+                        self.updated_fields.insert(field.to_owned(), value.to_owned());
+                    }
+                }
+                ("$unset", &Bson::Document(ref fields)) => {
+                    self.removed_fields.extend(fields.keys().cloned());
+                }
+                _ => return Err(Error::InvalidOperation),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Apply a `$v: 2` delta to the document at the given path.
+    fn apply_diff(&mut self, path: &str, diff: &Document) -> Result<()> {
+        for (key, value) in diff.iter() {
+            match (key.as_str(), value) {
+                ("u", &Bson::Document(ref fields)) |
+                ("i", &Bson::Document(ref fields)) => {
+                    for (field, value) in fields.iter() {
+                        self.updated_fields.insert(join(path, field), value.to_owned());
+                    }
+                }
+                ("d", &Bson::Document(ref fields)) => {
+                    self.removed_fields.extend(fields.keys().map(|field| join(path, field)));
+                }
+                (key, &Bson::Document(ref subdiff)) if key.starts_with('s') => {
+                    self.apply_subdiff(&join(path, &key[1..]), subdiff)?
+                }
+                _ => return Err(Error::InvalidOperation),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Apply a `$v: 2` delta to the array at the given path.
+    fn apply_array_diff(&mut self, path: &str, diff: &Document) -> Result<()> {
+        for (key, value) in diff.iter() {
+            match (key.as_str(), value) {
+                ("a", _) => {}
+                ("l", &Bson::I32(size)) => self.truncate(path, size as u32),
+                ("l", &Bson::I64(size)) => self.truncate(path, size as u32),
+                (key, &Bson::Document(ref subdiff)) if key.starts_with('s') => {
+                    self.apply_subdiff(&join(path, &key[1..]), subdiff)?
+                }
+                (key, value) if key.starts_with('u') => {
+                    self.updated_fields.insert(join(path, &key[1..]), value.to_owned());
+                }
+                _ => return Err(Error::InvalidOperation),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Apply a nested delta to either a document or, if marked as such, an array.
+    fn apply_subdiff(&mut self, path: &str, subdiff: &Document) -> Result<()> {
+        if subdiff.get_bool("a").unwrap_or(false) {
+            self.apply_array_diff(path, subdiff)
+        } else {
+            self.apply_diff(path, subdiff)
+        }
+    }
+
+    /// Record the truncation of the array at the given path.
+    fn truncate(&mut self, path: &str, new_size: u32) {
+        self.truncated_arrays.push(TruncatedArray {
+            field: path.to_owned(),
+            new_size: new_size,
+        });
+    }
+}
+
+/// Returns the dotted path of a field within the document at the given path.
+fn join(path: &str, field: &str) -> String {
+    if path.is_empty() {
+        field.to_owned()
+    } else {
+        format!("{}.{}", path, field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use Error;
+    use super::{TruncatedArray, UpdateDescription};
+
+    #[test]
+    fn update_description_converts_set_and_unset() {
+        let update = doc! {
+            "$v" => 1,
+            "$set" => { "foo" => "bar", "baz.quux" => 1 },
+            "$unset" => { "qux" => true }
+        };
+        let description = UpdateDescription::new(&update).unwrap();
+
+        assert_eq!(description,
+                   UpdateDescription {
+                       updated_fields: doc! { "foo" => "bar", "baz.quux" => 1 },
+                       removed_fields: vec!["qux".into()],
+                       truncated_arrays: vec![],
+                   });
+    }
+
+    #[test]
+    fn update_description_converts_unversioned_updates() {
+        let update = doc! { "$set" => { "foo" => "baz" } };
+        let description = UpdateDescription::new(&update).unwrap();
+
+        assert_eq!(description.updated_fields, doc! { "foo" => "baz" });
+    }
+
+    #[test]
+    fn update_description_converts_deltas() {
+        let update = doc! {
+            "$v" => 2,
+            "diff" => {
+                "d" => { "qux" => false },
+                "u" => { "foo" => "bar" },
+                "i" => { "new" => 1 },
+                "sbaz" => {
+                    "u" => { "quux" => 2 },
+                    "snested" => { "d" => { "deep" => false } }
+                }
+            }
+        };
+        let description = UpdateDescription::new(&update).unwrap();
+
+        assert_eq!(description,
+                   UpdateDescription {
+                       updated_fields: doc! { "foo" => "bar", "new" => 1, "baz.quux" => 2 },
+                       removed_fields: vec!["qux".into(), "baz.nested.deep".into()],
+                       truncated_arrays: vec![],
+                   });
+    }
+
+    #[test]
+    fn update_description_converts_array_deltas() {
+        let update = doc! {
+            "$v" => 2,
+            "diff" => {
+                "stags" => {
+                    "a" => true,
+                    "l" => 3,
+                    "u1" => "updated",
+                    "s2" => { "u" => { "name" => "nested" } }
+                }
+            }
+        };
+        let description = UpdateDescription::new(&update).unwrap();
+
+        assert_eq!(description,
+                   UpdateDescription {
+                       updated_fields: doc! { "tags.1" => "updated", "tags.2.name" => "nested" },
+                       removed_fields: vec![],
+                       truncated_arrays: vec![TruncatedArray {
+                                                  field: "tags".into(),
+                                                  new_size: 3,
+                                              }],
+                   });
+    }
+
+    #[test]
+    fn update_description_rejects_replacements() {
+        let update = doc! { "_id" => 1, "foo" => "bar" };
+
+        match UpdateDescription::new(&update) {
+            Err(Error::InvalidOperation) => {}
+            _ => panic!("Expected invalid operation."),
+        }
+    }
+}